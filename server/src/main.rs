@@ -2,7 +2,6 @@ use futures::future::BoxFuture;
 use nikidb::db::DB;
 use nikidb::db::DEFAULT_OPTIONS;
 use nikidb::error::{NKError, NKResult};
-use nikidb::tx::Tx;
 use redcon::cmd::Command;
 use redcon::connection::Connection;
 use redcon::frame::Frame;
@@ -18,14 +17,14 @@ async fn main() {
     print_banner();
 
     let db = DB::open("./test.db", DEFAULT_OPTIONS).unwrap();
-    db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+    db.update(|tx| -> NKResult<()> {
         match tx.create_bucket("default".as_bytes()) {
             Ok(_) => println!("create default bucket success"),
             Err(NKError::ErrBucketExists(e)) => println!("{} bucket exist", e),
             Err(e) => panic!("error"),
         }
         Ok(())
-    }))
+    })
     .unwrap();
 
     let handler = Handler { db };
@@ -49,33 +48,26 @@ impl AsyncFn for Handler {
         Box::pin(async {
             match _cmd {
                 Command::Get(_cmd) => {
-                    let v = {
-                        let mut x: Option<String> = None;
-                        self.db
-                            .view(Box::new(|tx: &mut Tx| -> NKResult<()> {
-                                let b = tx.bucket("default".as_bytes())?;
-                                x = match b.get(_cmd.key.as_bytes()) {
-                                    Some(v) => Some(String::from_utf8(v.to_vec()).unwrap()),
-                                    None => Some("not found".to_owned()),
-                                };
-                                Ok(())
-                            }))
-                            .unwrap();
-                        x
-                    };
-                    let resp = Frame::Simple(v.unwrap());
+                    let v = self
+                        .db
+                        .view(|tx| -> NKResult<String> {
+                            let b = tx.bucket("default".as_bytes())?;
+                            Ok(match b.get(_cmd.key.as_bytes())? {
+                                Some(v) => String::from_utf8(v.to_vec()).unwrap(),
+                                None => "not found".to_owned(),
+                            })
+                        })
+                        .unwrap();
+                    let resp = Frame::Simple(v);
                     _conn.write_frame(&resp).await.unwrap();
                 }
                 Command::Set(_cmd) => {
-                    {
-                        self.db
-                            .update(Box::new(|tx: &mut Tx| -> NKResult<()> {
-                                let b = tx.bucket("default".as_bytes())?;
-                                b.put(_cmd.key.as_bytes(), &_cmd.value).unwrap();
-                                Ok(())
-                            }))
-                            .unwrap();
-                    }
+                    self.db
+                        .update(|tx| -> NKResult<()> {
+                            let b = tx.bucket("default".as_bytes())?;
+                            b.put(_cmd.key.as_bytes(), &_cmd.value)
+                        })
+                        .unwrap();
                     let resp = Frame::Simple("OK".to_string());
                     _conn.write_frame(&resp).await.unwrap();
                 }
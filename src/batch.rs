@@ -0,0 +1,152 @@
+use crate::db::DB;
+use crate::error::NKResult;
+use crate::tx::Tx;
+use parking_lot::Mutex;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Once};
+
+pub(crate) type BatchFn = Arc<dyn Fn(&mut Tx) -> NKResult<()> + Send + Sync>;
+
+pub(crate) struct Call {
+    pub(crate) f: BatchFn,
+    //true 表示已经随批次提交，false 表示调用方需要单独重试
+    pub(crate) done: Sender<bool>,
+}
+
+pub(crate) struct Batch {
+    pub(crate) calls: Mutex<Vec<Call>>,
+    start: Once,
+}
+
+impl Batch {
+    pub(crate) fn new() -> Batch {
+        Self {
+            calls: Mutex::new(Vec::new()),
+            start: Once::new(),
+        }
+    }
+
+    //定时器和批次满了都会触发，只执行一次
+    pub(crate) fn trigger(self: &Arc<Self>, db: &DB) {
+        self.start.call_once(|| self.run(db));
+    }
+
+    fn run(self: &Arc<Self>, db: &DB) {
+        //从 db 上摘掉当前批次，之后的调用会进入新的批次
+        {
+            let mut batch = db.0.batch.lock();
+            if batch.as_ref().is_some_and(|b| Arc::ptr_eq(b, self)) {
+                batch.take();
+            }
+        }
+        let mut calls = std::mem::take(&mut *self.calls.lock());
+        while !calls.is_empty() {
+            let mut fail_idx = None;
+            let res = db.update(|tx| -> NKResult<()> {
+                for (i, c) in calls.iter().enumerate() {
+                    if let Err(e) = (c.f)(tx) {
+                        fail_idx = Some(i);
+                        return Err(e);
+                    }
+                }
+                Ok(())
+            });
+            //出错的调用交给调用方单独重试，剩下的重新执行
+            if let Some(i) = fail_idx {
+                let c = calls.remove(i);
+                let _ = c.done.send(false);
+                continue;
+            }
+            let ok = res.is_ok();
+            for c in calls.drain(..) {
+                let _ = c.done.send(ok);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DB, DEFAULT_OPTIONS};
+    use crate::error::{NKError, NKResult};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_db_batch() {
+        let options = DEFAULT_OPTIONS.max_batch_delay(Duration::from_millis(50));
+        let db = DB::open(&crate::test_db_path("batch"), options).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            tx.create_bucket(b"default")?;
+            Ok(())
+        })
+        .unwrap();
+        let txid = db.0.meta().unwrap().txid;
+
+        let handles: Vec<_> = (0..20u32)
+            .map(|i| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    db.batch(move |tx| {
+                        //第 13 个调用失败，不影响同一批次里的其他调用
+                        if i == 13 {
+                            return Err(NKError::ErrKeyRequired);
+                        }
+                        tx.bucket(b"default")?
+                            .put(format!("key{:02}", i).as_bytes(), b"value")
+                    })
+                })
+            })
+            .collect();
+        for (i, h) in handles.into_iter().enumerate() {
+            let res = h.join().unwrap();
+            if i == 13 {
+                assert!(matches!(res, Err(NKError::ErrKeyRequired)));
+            } else {
+                assert!(res.is_ok());
+            }
+        }
+        //20 个调用合并成了少数几个事务
+        assert!(db.0.meta().unwrap().txid - txid < 10);
+
+        db.view(|tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            for i in 0..20u32 {
+                let v = b.get(format!("key{:02}", i).as_bytes())?;
+                assert_eq!(v.is_some(), i != 13);
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_db_batch_max_size() {
+        let options = DEFAULT_OPTIONS
+            .max_batch_size(2)
+            .max_batch_delay(Duration::from_secs(10));
+        let db = DB::open(&crate::test_db_path("batch_max_size"), options).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            tx.create_bucket(b"default")?;
+            Ok(())
+        })
+        .unwrap();
+        //批次满了就提交，不用等 10 秒
+        let handles: Vec<_> = (0..2u32)
+            .map(|i| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    db.batch(move |tx| tx.bucket(b"default")?.put(&[i as u8], b"value"))
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap().unwrap();
+        }
+        assert!(DB::open(
+            &crate::test_db_path("batch_size_0"),
+            DEFAULT_OPTIONS.max_batch_size(0)
+        )
+        .is_err());
+    }
+}
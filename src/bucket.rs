@@ -1,14 +1,22 @@
-use crate::cursor::Cursor;
+use crate::comparator::{Comparator, BYTEWISE};
+use crate::compression::Compression;
+use crate::cursor::{Cursor, Range};
 use crate::error::{NKError, NKResult};
 use crate::node::{Node, NodeImpl};
-use crate::page::{BranchPageFlag, BucketLeafFlag, LeafPageElementSize, OwnerPage, Page, Pgid};
+use crate::page::{
+    BranchPageElementSize, BranchPageFlag, BucketLeafFlag, CompressedLeafFlag, FeatureComparator,
+    FeatureCompression, LeafPageElementSize, LeafPageFlag, OwnerPage, Page, Pgid,
+};
 use crate::tx::TxImpl;
+use crate::wal::Op;
 
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::mem::size_of;
+use std::ops::RangeBounds;
 
-use std::rc::Weak;
+use std::rc::{Rc, Weak};
 use std::sync::{Arc, Weak as ArcWeak};
 
 pub(crate) const BucketHeaderSize: usize = size_of::<IBucket>();
@@ -21,17 +29,26 @@ pub(crate) const MIN_FILL_PERCENT: f64 = 0.1;
 
 pub(crate) const MAX_FILL_PERCENT: f64 = 1.0;
 
-const DEFAULT_FILL_PERCENT: f64 = 0.5;
+pub(crate) const DEFAULT_FILL_PERCENT: f64 = 0.5;
 
 pub struct Bucket {
     pub(crate) ibucket: IBucket,
     pub(crate) nodes: RefCell<HashMap<Pgid, Node>>,
     pub(crate) weak_tx: ArcWeak<TxImpl>,
-    root_node: Option<Node>,
+    root_node: RefCell<Option<Node>>,
     page: Option<OwnerPage>, // inline page
-    buckets: RefCell<HashMap<Vec<u8>, Bucket>>,
+    buckets: RefCell<HashMap<Vec<u8>, Box<Bucket>>>,
 
     pub(crate) fill_percent: f64,
+
+    pub(crate) comparator: Comparator,
+
+    compression: Compression,
+
+    //从根 bucket 到当前 bucket 的 key，WAL 记录用它定位 bucket
+    path: Vec<Vec<u8>>,
+    //路径上第一个不是内置的比较器，WAL 重放时打不开这个 bucket
+    custom_comparator: Option<u16>,
 }
 
 #[derive(Clone)]
@@ -46,8 +63,60 @@ impl From<Node> for PageNode {
     }
 }
 
+/// Page and key statistics of a bucket and its nested buckets, see `Bucket::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BucketStats {
+    /// Number of branch pages.
+    pub branch_page_n: usize,
+    /// Number of overflow pages of the branch pages.
+    pub branch_overflow_n: usize,
+    /// Number of leaf pages.
+    pub leaf_page_n: usize,
+    /// Number of overflow pages of the leaf pages.
+    pub leaf_overflow_n: usize,
+    /// Number of keys, nested buckets included.
+    pub key_n: usize,
+    /// Number of levels of the tree.
+    pub depth: usize,
+    /// Bytes allocated for branch pages.
+    pub branch_alloc: usize,
+    /// Bytes actually used by branch pages.
+    pub branch_inuse: usize,
+    /// Bytes allocated for leaf pages.
+    pub leaf_alloc: usize,
+    /// Bytes actually used by leaf pages.
+    pub leaf_inuse: usize,
+    /// Number of buckets, this one included.
+    pub bucket_n: usize,
+    /// Number of inline buckets.
+    pub inline_bucket_n: usize,
+    /// Bytes used by inline buckets.
+    pub inline_bucket_inuse: usize,
+}
+
+impl BucketStats {
+    pub fn add(&mut self, other: &BucketStats) {
+        self.branch_page_n += other.branch_page_n;
+        self.branch_overflow_n += other.branch_overflow_n;
+        self.leaf_page_n += other.leaf_page_n;
+        self.leaf_overflow_n += other.leaf_overflow_n;
+        self.key_n += other.key_n;
+        self.depth = self.depth.max(other.depth);
+        self.branch_alloc += other.branch_alloc;
+        self.branch_inuse += other.branch_inuse;
+        self.leaf_alloc += other.leaf_alloc;
+        self.leaf_inuse += other.leaf_inuse;
+        self.bucket_n += other.bucket_n;
+        self.inline_bucket_n += other.inline_bucket_n;
+        self.inline_bucket_inuse += other.inline_bucket_inuse;
+    }
+}
+
 impl Bucket {
     pub(crate) fn new(root: Pgid, tx: ArcWeak<TxImpl>) -> Bucket {
+        let fill_percent = tx
+            .upgrade()
+            .map_or(DEFAULT_FILL_PERCENT, |tx| tx.db().fill_percent());
         Self {
             ibucket: IBucket {
                 root: root,
@@ -55,36 +124,110 @@ impl Bucket {
             },
             nodes: RefCell::new(HashMap::new()),
             weak_tx: tx,
-            root_node: None,
+            root_node: RefCell::new(None),
             page: None,
             buckets: RefCell::new(HashMap::new()),
-            fill_percent: DEFAULT_FILL_PERCENT,
+            fill_percent,
+            comparator: BYTEWISE,
+            compression: Compression::None,
+            path: Vec::new(),
+            custom_comparator: None,
         }
     }
 
-    pub(crate) fn bucket(&mut self, key: &[u8]) -> NKResult<*mut Bucket> {
+    /// Retrieves a nested bucket by name.
+    pub fn bucket(&mut self, key: &[u8]) -> NKResult<&mut Bucket> {
+        self.bucket_with_comparator(key, BYTEWISE)
+    }
+
+    /// Retrieves a nested bucket created with `comparator`. Returns
+    /// `ErrComparatorMismatch` if the bucket was created with another one.
+    pub fn bucket_with_comparator(
+        &mut self,
+        key: &[u8],
+        comparator: Comparator,
+    ) -> NKResult<&mut Bucket> {
+        self.child(key, comparator).map(|b| unsafe { &mut *b })
+    }
+
+    /// Retrieves a nested bucket for reading. Unlike `bucket`, several nested
+    /// buckets and the values read from them can be held at the same time.
+    pub fn bucket_ref(&self, key: &[u8]) -> NKResult<&Bucket> {
+        self.bucket_ref_with_comparator(key, BYTEWISE)
+    }
+
+    /// Like `bucket_ref`, for a nested bucket created with `comparator`.
+    pub fn bucket_ref_with_comparator(
+        &self,
+        key: &[u8],
+        comparator: Comparator,
+    ) -> NKResult<&Bucket> {
+        self.child(key, comparator).map(|b| unsafe { &*b })
+    }
+
+    //子 bucket 装箱后缓存，插入新的子 bucket 不会移动已经返回的引用
+    fn child(&self, key: &[u8], comparator: Comparator) -> NKResult<*mut Bucket> {
         if let Some(bucket) = self.buckets.borrow_mut().get_mut(key) {
-            return Ok(bucket);
+            if bucket.comparator.id != comparator.id {
+                return Err(NKError::ErrComparatorMismatch(bucket.comparator.id));
+            }
+            return Ok(&mut **bucket as *mut Bucket);
         }
         let value = {
-            let mut c = self.cursor();
+            let mut c = Cursor::new(self);
             let item = c.seek_item(key)?;
-            if !key.eq(item.0.unwrap()) || (item.2 & BucketLeafFlag) == 0 {
+            if !item.0.is_some_and(|k| self.comparator.eq(k, key)) || (item.2 & BucketLeafFlag) == 0
+            {
                 return Err(NKError::ErrBucketNotFound);
             }
-            item.1.unwrap().to_vec()
+            let id = Comparator::id_from_flags(item.2);
+            if id != comparator.id {
+                return Err(NKError::ErrComparatorMismatch(id));
+            }
+            (item.1.unwrap().to_vec(), item.2)
         };
-        let child = self.open_bucket(value)?;
+        let mut child = Box::new(self.open_bucket(value.0, comparator)?);
+        child.compression = Compression::from_flags(value.1)?;
+        child.path = self.path.clone();
+        child.path.push(key.to_vec());
+        child.custom_comparator = self
+            .custom_comparator
+            .or(Some(comparator.id).filter(|&id| Comparator::builtin(id).is_none()));
+        let ptr = &mut *child as *mut Bucket;
         self.buckets.borrow_mut().insert(key.to_vec(), child);
-        if let Some(bucket) = self.buckets.borrow_mut().get_mut(key) {
-            return Ok(bucket);
-        }
+        Ok(ptr)
+    }
+
+    //按保存的比较器 id 打开子 bucket，只支持内置的比较器
+    pub(crate) fn stored_bucket(&mut self, key: &[u8]) -> NKResult<&mut Bucket> {
+        let cmp = Self::stored_comparator(Cursor::new(self).seek_item(key)?.2)?;
+        self.bucket_with_comparator(key, cmp)
+    }
 
-        return Err(NKError::ErrBucketNotFound);
+    fn stored_comparator(flags: u32) -> NKResult<Comparator> {
+        let id = Comparator::id_from_flags(flags);
+        Comparator::builtin(id).ok_or(NKError::ErrComparatorMismatch(id))
     }
 
-    fn open_bucket(&self, value: Vec<u8>) -> NKResult<Bucket> {
+    //按叶子元素 flags 里保存的比较器和压缩方式打开子 bucket
+    fn open_stored(&self, value: Vec<u8>, flags: u32) -> NKResult<Bucket> {
+        let mut child = self.open_bucket(value, Self::stored_comparator(flags)?)?;
+        child.compression = Compression::from_flags(flags)?;
+        Ok(child)
+    }
+
+    //WAL 模式下记录修改，提交时写进日志
+    pub(crate) fn log(&self, op: impl FnOnce(Vec<Vec<u8>>) -> Op) {
+        if let Some(tx) = self.tx() {
+            if tx.db().wal.is_some() {
+                tx.wal_ops.borrow_mut().push(op(self.path.clone()));
+            }
+        }
+    }
+
+    fn open_bucket(&self, value: Vec<u8>, comparator: Comparator) -> NKResult<Bucket> {
         let mut child = Bucket::new(0, self.weak_tx.clone());
+        child.comparator = comparator;
         let ibucket = crate::u8_to_struct::<IBucket>(value.as_slice());
         child.ibucket = ibucket.clone();
         if child.ibucket.root == 0 {
@@ -95,31 +238,225 @@ impl Bucket {
         Ok(child)
     }
 
-    pub(crate) fn create_bucket(&mut self, key: &[u8]) -> NKResult<*mut Bucket> {
+    /// Creates a new nested bucket. Returns an error if the bucket already exists
+    /// or if the key is already used by a value.
+    pub fn create_bucket(&mut self, key: &[u8]) -> NKResult<&mut Bucket> {
+        self.create_bucket_with_comparator(key, BYTEWISE)
+    }
+
+    /// Creates a new nested bucket whose keys are ordered by `comparator`. The
+    /// same comparator must be passed to `bucket_with_comparator` to open it.
+    pub fn create_bucket_with_comparator(
+        &mut self,
+        key: &[u8],
+        comparator: Comparator,
+    ) -> NKResult<&mut Bucket> {
+        self.check_writable()?;
+        if key.is_empty() {
+            return Err(NKError::ErrKeyRequired);
+        } else if Comparator::reserved(comparator.id) {
+            return Err(NKError::ErrComparatorReserved(comparator.id));
+        } else if self.tx().unwrap().db().wal.is_some()
+            && Comparator::builtin(comparator.id).is_none()
+        {
+            return Err(NKError::ErrWalComparator(comparator.id));
+        }
         let tx_clone = self.weak_tx.clone();
+        let cmp = self.comparator;
         let mut c = self.cursor();
-        let item = c.seek(key)?;
-        if item.key().eq(&Some(key)) {
+        let item = c.seek_raw(key)?;
+        if item.key().is_some_and(|k| cmp.eq(k, key)) {
             if item.flags() & BucketLeafFlag != 0 {
                 return Err(NKError::ErrBucketExists(
                     String::from_utf8_lossy(key).into(),
                 ));
             }
+            return Err(NKError::IncompatibleValue);
         }
         let mut bucket = Bucket::new(0, tx_clone); // root == 0 is inline bucket
-        bucket.root_node = Some(NodeImpl::new().leaf(true).build());
+        *bucket.root_node.get_mut() =
+            Some(NodeImpl::new().leaf(true).comparator(comparator).build());
         let value = bucket.write();
 
-        c.node()?.put(key, key, value.as_slice(), 0, BucketLeafFlag);
+        c.node()?
+            .put(key, key, value.as_slice(), 0, comparator.bucket_flags());
+        //不认识比较器 id 的旧版本会按字节序读取这个 bucket
+        if comparator.id != BYTEWISE.id {
+            self.tx().unwrap().meta.borrow_mut().flags |= FeatureComparator;
+        }
+        self.log(|path| Op::CreateBucket {
+            path,
+            key: key.to_vec(),
+            comparator: comparator.id,
+        });
+
+        self.bucket_with_comparator(key, comparator)
+    }
 
-        self.bucket(key)
+    /// Creates a new nested bucket if it doesn't already exist and returns it.
+    pub fn create_bucket_if_not_exists(&mut self, key: &[u8]) -> NKResult<&mut Bucket> {
+        self.check_writable()?;
+        let bucket = match self.bucket(key) {
+            Ok(b) => b as *mut Bucket,
+            Err(NKError::ErrBucketNotFound) => self.create_bucket(key)? as *mut Bucket,
+            Err(e) => return Err(e),
+        };
+        Ok(unsafe { &mut *bucket })
     }
 
-    fn cursor(&mut self) -> Cursor {
+    /// Deletes a nested bucket and all of its nested buckets. The pages they
+    /// used are released to the freelist when the transaction commits.
+    pub fn delete_bucket(&mut self, key: &[u8]) -> NKResult<()> {
+        self.check_writable()?;
+        {
+            let cmp = self.comparator;
+            let mut c = self.cursor();
+            let item = c.seek_raw(key)?;
+            if !item.key().is_some_and(|k| cmp.eq(k, key)) {
+                return Err(NKError::ErrBucketNotFound);
+            } else if !item.is_bucket() {
+                return Err(NKError::IncompatibleValue);
+            }
+        }
+        self.take_bucket(key)?.free_all()?;
+
+        let mut c = self.cursor();
+        c.seek_raw(key)?;
+        c.node()?.del(key);
+        self.log(|path| Op::DeleteBucket {
+            path,
+            key: key.to_vec(),
+        });
+        Ok(())
+    }
+
+    //从缓存中取出子 bucket，没有缓存时按存储的内容打开
+    fn take_bucket(&mut self, key: &[u8]) -> NKResult<Bucket> {
+        if let Some(child) = self.buckets.borrow_mut().remove(key) {
+            return Ok(*child);
+        }
+        let (value, flags) = {
+            let mut c = self.cursor();
+            let item = c.seek_item(key)?;
+            (item.1.ok_or(NKError::ErrBucketNotFound)?.to_vec(), item.2)
+        };
+        self.open_stored(value, flags)
+    }
+
+    //递归释放 bucket 和所有子 bucket 占用的页面。只按顺序遍历 key，不需要比较器
+    fn free_all(&mut self) -> NKResult<()> {
+        let mut names: Vec<Vec<u8>> = Vec::new();
+        {
+            let mut c = self.cursor();
+            let mut item = c.first()?;
+            while let Some(k) = item.key() {
+                if item.is_bucket() {
+                    names.push(k.to_vec());
+                }
+                item = c.next()?;
+            }
+        }
+        for name in names.iter() {
+            self.take_bucket(name)?.free_all()?;
+        }
+        //丢弃已经修改过的节点，释放 bucket 占用的所有页面
+        self.nodes.borrow_mut().clear();
+        *self.root_node.get_mut() = None;
+        self.free()
+    }
+
+    /// Returns the current sequence number of the bucket.
+    pub fn sequence(&self) -> u64 {
+        self.ibucket.sequence
+    }
+
+    /// Updates the sequence number of the bucket.
+    pub fn set_sequence(&mut self, v: u64) -> NKResult<()> {
+        self.check_writable()?;
+        self.materialize_root()?;
+        self.ibucket.sequence = v;
+        self.log(|path| Op::SetSequence { path, sequence: v });
+        Ok(())
+    }
+
+    /// Compresses the values put into this bucket from now on. Values already
+    /// stored keep their form and are still read back transparently. Returns
+    /// `ErrCompressionUnsupported` if the algorithm's cargo feature is off.
+    ///
+    /// The setting is not stored in the bucket header but in bits 8-15 of the
+    /// flags of the bucket's element in its parent, next to the comparator id.
+    /// The header is also part of the meta page and sets where inline buckets
+    /// start, so growing it would change the layout of every existing bucket.
+    /// Once a value is stored compressed the file is marked with a format
+    /// feature flag, and older versions refuse to open it instead of returning
+    /// compressed bytes.
+    pub fn set_compression(&mut self, compression: Compression) -> NKResult<()> {
+        self.check_writable()?;
+        compression.check()?;
+        self.materialize_root()?;
+        self.compression = compression;
+        self.log(|path| Op::SetCompression {
+            path,
+            compression: compression as u8,
+        });
+        Ok(())
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Increments the sequence number of the bucket and returns the new value.
+    pub fn next_sequence(&mut self) -> NKResult<u64> {
+        self.set_sequence(self.ibucket.sequence + 1)?;
+        Ok(self.ibucket.sequence)
+    }
+
+    //加载根节点，保证提交时 bucket 头部会被重新写入
+    fn materialize_root(&mut self) -> NKResult<()> {
+        if self.root_node.borrow().is_none() {
+            self.node(self.ibucket.root, None)?;
+        }
+        Ok(())
+    }
+
+    /// Creates a cursor over the key/value pairs of this bucket.
+    pub fn cursor(&mut self) -> Cursor<'_> {
         Cursor::new(self)
     }
 
+    /// Iterates over the key/value pairs whose keys fall in `range`, in ascending order.
+    pub fn range<K, R>(&self, range: R) -> Range<'_>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        Range::new(Cursor::new(self), range, false)
+    }
+
+    /// Iterates over the key/value pairs whose keys fall in `range`, in descending order.
+    pub fn range_rev<K, R>(&self, range: R) -> Range<'_>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        Range::new(Cursor::new(self), range, true)
+    }
+
+    /// Iterates over the key/value pairs whose keys start with `prefix`, in ascending order.
+    /// Only meaningful for buckets ordered byte-wise.
+    pub fn prefix(&self, prefix: &[u8]) -> Range<'_> {
+        Range::prefix(Cursor::new(self), prefix, false)
+    }
+
+    /// Iterates over the key/value pairs whose keys start with `prefix`, in descending order.
+    /// Only meaningful for buckets ordered byte-wise.
+    pub fn prefix_rev(&self, prefix: &[u8]) -> Range<'_> {
+        Range::prefix(Cursor::new(self), prefix, true)
+    }
+
     pub fn put(&mut self, key: &[u8], value: &[u8]) -> NKResult<()> {
+        self.check_writable()?;
         if key.len() == 0 {
             return Err(NKError::ErrKeyRequired);
         } else if key.len() > MAX_KEY_SIZE {
@@ -127,33 +464,131 @@ impl Bucket {
         } else if value.len() > MAX_VALUE_SIZE {
             return Err(NKError::ErrValueTooLarge);
         }
+        let compressed = self.compression.compress(value)?;
+        if compressed.is_some() {
+            self.tx().unwrap().meta.borrow_mut().flags |= FeatureCompression;
+        }
 
+        let cmp = self.comparator;
         let mut c = self.cursor();
-        let item = c.seek(key)?;
+        let item = c.seek_raw(key)?;
 
-        if Some(key) == item.0 && (item.2 & BucketLeafFlag) == 1 {
+        if item.0.is_some_and(|k| cmp.eq(k, key)) && (item.2 & BucketLeafFlag) == 1 {
             return Err(NKError::IncompatibleValue);
         }
-        c.node()?.put(key, key, value, 0, 0);
+        match &compressed {
+            Some(v) => c.node()?.put(key, key, v, 0, CompressedLeafFlag),
+            None => c.node()?.put(key, key, value, 0, 0),
+        }
+        self.log(|path| Op::Put {
+            path,
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
         Ok(())
     }
 
-    pub fn get(&mut self, key: &[u8]) -> Option<&[u8]> {
-        let mut c = self.cursor();
-        let item = c.seek(key).unwrap();
-        if Some(key) == item.0 && (item.2 & BucketLeafFlag) == 1 {
-            return None;
+    /// Returns the value of `key`. The value points into the database file or
+    /// the transaction's dirty nodes, and lives as long as the bucket borrow;
+    /// only values stored compressed are returned owned.
+    /// Fails if a page on the way can't be read, e.g. on a checksum mismatch.
+    pub fn get(&self, key: &[u8]) -> NKResult<Option<Cow<'_, [u8]>>> {
+        let mut c = Cursor::new(self);
+        let item = c.seek_raw(key)?;
+        if !item.0.is_some_and(|k| self.comparator.eq(k, key)) || (item.2 & BucketLeafFlag) == 1 {
+            return Ok(None);
         }
-        item.1
+        Ok(c.output(item)?.1)
     }
 
     pub fn delete(&mut self, key: &[u8]) -> NKResult<()> {
+        self.check_writable()?;
         let mut c = self.cursor();
-        let item = c.seek(key)?;
+        let item = c.seek_raw(key)?;
         if item.flags() & BucketLeafFlag != 0 {
             return Err(NKError::IncompatibleValue);
         }
         c.node()?.del(key);
+        self.log(|path| Op::Delete {
+            path,
+            key: key.to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Returns page and key statistics of the bucket and its nested buckets,
+    /// as of the last commit.
+    pub fn stats(&self) -> NKResult<BucketStats> {
+        let mut s = BucketStats::default();
+        let mut sub = BucketStats::default();
+        let page_size = self.tx().ok_or("tx closed")?.db().get_page_size();
+        s.bucket_n += 1;
+        if self.ibucket.root == 0 {
+            s.inline_bucket_n += 1;
+        }
+        self.for_each_page(&mut |p, depth| -> NKResult<()> {
+            if p.flags & LeafPageFlag != 0 {
+                s.key_n += p.count();
+                let elems = p.leaf_page_elements();
+                let used = Page::header_size_for(elems.len())
+                    + elems
+                        .iter()
+                        .map(|e| LeafPageElementSize + (e.ksize + e.vsize) as usize)
+                        .sum::<usize>();
+                if self.ibucket.root == 0 {
+                    s.inline_bucket_inuse += used;
+                } else {
+                    s.leaf_page_n += 1;
+                    s.leaf_inuse += used;
+                    s.leaf_overflow_n += p.overflow as usize;
+                    //子 bucket 单独统计
+                    for e in elems.iter().filter(|e| e.flags & BucketLeafFlag != 0) {
+                        sub.add(&self.open_stored(e.value().to_vec(), e.flags)?.stats()?);
+                    }
+                }
+            } else if p.flags & BranchPageFlag != 0 {
+                s.branch_page_n += 1;
+                s.branch_inuse += Page::header_size_for(p.count())
+                    + p.branch_page_elements()
+                        .iter()
+                        .map(|e| BranchPageElementSize + e.ksize as usize)
+                        .sum::<usize>();
+                s.branch_overflow_n += p.overflow as usize;
+            }
+            s.depth = s.depth.max(depth + 1);
+            Ok(())
+        })?;
+        s.branch_alloc = (s.branch_page_n + s.branch_overflow_n) * page_size;
+        s.leaf_alloc = (s.leaf_page_n + s.leaf_overflow_n) * page_size;
+        s.add(&sub);
+        Ok(s)
+    }
+
+    //只遍历已经写入文件的页，不包括事务中还没提交的节点
+    fn for_each_page<F>(&self, f: &mut F) -> NKResult<()>
+    where
+        F: FnMut(&Page, usize) -> NKResult<()>,
+    {
+        if self.ibucket.root == 0 {
+            if let Some(p) = &self.page {
+                f(p.to_page(), 0)?;
+            }
+            return Ok(());
+        }
+        self._for_each_page(self.ibucket.root, 0, f)
+    }
+
+    fn _for_each_page<F>(&self, pgid: Pgid, depth: usize, f: &mut F) -> NKResult<()>
+    where
+        F: FnMut(&Page, usize) -> NKResult<()>,
+    {
+        let p = unsafe { &*self.tx().ok_or("tx closed")?.page(pgid)? };
+        f(p, depth)?;
+        if p.flags & BranchPageFlag != 0 {
+            for e in p.branch_page_elements() {
+                self._for_each_page(e.pgid, depth + 1, f)?;
+            }
+        }
         Ok(())
     }
 
@@ -200,7 +635,7 @@ impl Bucket {
             PageNode::Page(p) => {
                 let page = unsafe { &**p };
                 if page.flags & BranchPageFlag != 0 {
-                    for i in 0..page.count as usize {
+                    for i in 0..page.count() {
                         let elem = page.branch_page_element(i);
                         self._for_each_page_node(elem.pgid, depth + 1, f)?;
                     }
@@ -224,7 +659,7 @@ impl Bucket {
             if id != 0 {
                 panic!("inline bucket non-zero page access(2): {} != 0", id)
             }
-            if let Some(n) = &self.root_node {
+            if let Some(n) = &*self.root_node.borrow() {
                 return Ok(PageNode::Node(n.clone()));
             }
             if let Some(p) = &self.page {
@@ -234,7 +669,7 @@ impl Bucket {
         if let Some(node) = self.nodes.borrow().get(&id) {
             return Ok(PageNode::Node(node.clone()));
         }
-        let page = self.tx().unwrap().db().page(id);
+        let page = self.tx().unwrap().page(id)?;
         Ok(PageNode::Page(page))
     }
 
@@ -242,8 +677,21 @@ impl Bucket {
         self.weak_tx.upgrade()
     }
 
+    //只读数据库和只读事务都不能修改数据
+    pub(crate) fn check_writable(&self) -> NKResult<()> {
+        let tx = self.tx().ok_or("tx closed")?;
+        if tx.db().read_only() {
+            return Err(NKError::ErrDatabaseReadOnly);
+        } else if !tx.writable {
+            return Err(NKError::ErrTxNotWritable);
+        } else if let Some(id) = self.custom_comparator.filter(|_| tx.db().wal.is_some()) {
+            return Err(NKError::ErrWalComparator(id));
+        }
+        Ok(())
+    }
+
     pub(crate) fn write(&self) -> Vec<u8> {
-        let n = self.root_node.as_ref().unwrap();
+        let n = self.root_node.borrow().clone().unwrap();
         let size = n.size();
         let mut value = vec![0u8; BucketHeaderSize + size];
 
@@ -257,36 +705,51 @@ impl Bucket {
         value
     }
 
-    pub(crate) fn node(&mut self, pgid: Pgid, parent: Option<Weak<RefCell<NodeImpl>>>) -> Node {
+    pub(crate) fn node(
+        &self,
+        pgid: Pgid,
+        parent: Option<Weak<RefCell<NodeImpl>>>,
+    ) -> NKResult<Node> {
         if let Some(node) = self.nodes.borrow().get(&pgid) {
-            return node.clone();
+            return Ok(node.clone());
         }
+        //先取页，校验失败时不会留下挂在父节点上的空节点
+        let page = if let Some(p) = &self.page {
+            p.to_page()
+        } else {
+            unsafe { &*self.tx().unwrap().page(pgid)? }
+        };
 
         let mut n = if let Some(p) = parent {
-            let n = NodeImpl::new().parent(p.clone()).build();
+            let n = NodeImpl::new()
+                .parent(p.clone())
+                .comparator(self.comparator)
+                .build();
             let parent_node = p.upgrade().map(Node).unwrap();
             parent_node.node_mut().children.push(n.clone());
             n
         } else {
-            let n = NodeImpl::new().build();
-            self.root_node.replace(n.clone());
+            let n = NodeImpl::new().comparator(self.comparator).build();
+            self.root_node.replace(Some(n.clone()));
             n
         };
 
-        let page = if let Some(p) = &self.page {
-            p.to_page()
-        } else {
-            let p = self.tx().unwrap().db().page(pgid);
-            unsafe { &*p }
-        };
         n.read(page);
         self.nodes.borrow_mut().insert(pgid, n.clone());
-        n
+        Ok(n)
     }
 
     pub(crate) fn rebalance(&mut self, page_size: usize) -> NKResult<()> {
         let nodes = self.nodes.clone();
         for n in nodes.borrow_mut().values_mut() {
+            //合并过程中已经被移除的节点不再处理
+            let removed = match self.nodes.borrow().get(&n.node().pgid) {
+                Some(cur) => !Rc::ptr_eq(&cur.0, &n.0),
+                None => true,
+            };
+            if removed {
+                continue;
+            }
             n.rebalance(page_size, self)?;
         }
 
@@ -301,7 +764,7 @@ impl Bucket {
     }
 
     fn inline_able(&self) -> bool {
-        if let Some(n) = &self.root_node {
+        if let Some(n) = &*self.root_node.borrow() {
             if !n.node().is_leaf {
                 return false;
             }
@@ -336,28 +799,35 @@ impl Bucket {
                 }
                 value
             };
-            if child.root_node.is_none() {
+            if child.root_node.borrow().is_none() {
                 continue;
             }
+            let cmp = root_bucket.comparator;
             let mut c = root_bucket.cursor();
-            let item = c.seek(name)?;
+            let item = c.seek_raw(name)?;
             if let Some(k) = item.0 {
-                if k != name {
+                if !cmp.eq(k, name) {
                     panic!("misplaced bucket header: {:?} -> {:?}", k, name);
                 }
             }
             if item.flags() & BucketLeafFlag == 0 {
                 panic!("unexpected bucket header flag: {}", item.flags());
             }
-            c.node()?
-                .put(name, name, value.as_slice(), 0, BucketLeafFlag);
+            let key = item.0.unwrap_or(name).to_vec();
+            c.node()?.put(
+                name,
+                &key,
+                value.as_slice(),
+                0,
+                child.comparator.bucket_flags() | child.compression.bucket_flags(),
+            );
         }
 
-        if let Some(n) = &self.root_node {
-            let mut root = n.clone();
-            let root_node = root.spill(atx, &self)?;
+        let root = self.root_node.borrow().clone();
+        if let Some(root) = root {
+            let root_node = root.spill(atx, self)?;
             self.ibucket.root = root_node.node().pgid;
-            self.root_node.replace(root_node);
+            self.root_node.replace(Some(root_node));
         }
 
         Ok(())
@@ -378,3 +848,454 @@ impl IBucket {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::comparator::{Comparator, BIG_ENDIAN, CASE_INSENSITIVE, REVERSE};
+    use crate::db::{DB, DEFAULT_OPTIONS};
+    use crate::error::{NKError, NKResult};
+    use crate::tx::Tx;
+
+    #[test]
+    fn test_nested_bucket() {
+        let path = crate::test_db_path("nested_bucket");
+        {
+            let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+            db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                let tenant = tx.create_bucket(b"tenant1")?;
+                let table = tenant.create_bucket(b"users")?;
+                for i in 0..500 {
+                    table.put(format!("user{:04}", i).as_bytes(), b"profile")?;
+                }
+                let index = table.create_bucket(b"by_mail")?;
+                index.put(b"a@b.c", b"user0001")?;
+                tenant.put(b"plan", b"free")?;
+                Ok(())
+            }))
+            .unwrap();
+        }
+        let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let tenant = tx.bucket(b"tenant1")?;
+            assert_eq!(tenant.get(b"plan")?.as_deref(), Some(&b"free"[..]));
+            assert!(matches!(
+                tenant.create_bucket(b"users"),
+                Err(NKError::ErrBucketExists(_))
+            ));
+            assert!(matches!(
+                tenant.create_bucket(b"plan"),
+                Err(NKError::IncompatibleValue)
+            ));
+            assert!(matches!(
+                tenant.bucket(b"plan"),
+                Err(NKError::ErrBucketNotFound)
+            ));
+            let table = tenant.create_bucket_if_not_exists(b"users")?;
+            assert_eq!(table.get(b"user0499")?.as_deref(), Some(&b"profile"[..]));
+            let index = table.bucket(b"by_mail")?;
+            assert_eq!(index.get(b"a@b.c")?.as_deref(), Some(&b"user0001"[..]));
+            let orders = tenant.create_bucket_if_not_exists(b"orders")?;
+            orders.put(b"1", b"x")?;
+            Ok(())
+        }))
+        .unwrap();
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let tenant = tx.bucket(b"tenant1")?;
+            assert_eq!(
+                tenant.bucket(b"orders")?.get(b"1")?.as_deref(),
+                Some(&b"x"[..])
+            );
+            let table = tenant.bucket(b"users")?;
+            assert_eq!(table.range::<[u8], _>(..).count(), 500);
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_delete_bucket() {
+        let db = DB::open(&crate::test_db_path("delete_bucket"), DEFAULT_OPTIONS).unwrap();
+        let fill = |tx: &mut Tx| -> NKResult<()> {
+            let a = tx.create_bucket(b"a")?;
+            a.put(b"k", b"v")?;
+            let b = a.create_bucket(b"b")?;
+            for i in 0..2000 {
+                b.put(format!("key{:05}", i).as_bytes(), &[0u8; 64])?;
+            }
+            Ok(())
+        };
+        db.update(Box::new(fill)).unwrap();
+        let mut high_water = 0;
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            assert!(matches!(
+                tx.delete_bucket(b"none"),
+                Err(NKError::ErrBucketNotFound)
+            ));
+            assert!(matches!(
+                tx.bucket(b"a")?.delete_bucket(b"k"),
+                Err(NKError::IncompatibleValue)
+            ));
+            tx.delete_bucket(b"a")?;
+            assert!(matches!(tx.bucket(b"a"), Err(NKError::ErrBucketNotFound)));
+            let txid = tx.0.meta.borrow().txid;
+            let db = tx.0.db();
+            let freelist = db.freelist.try_read().unwrap();
+            assert!(freelist.pending.get(&txid).map_or(0, |ids| ids.len()) > 30);
+            high_water = tx.0.meta.borrow().pgid;
+            Ok(())
+        }))
+        .unwrap();
+        //释放的页面会被重新使用
+        db.update(Box::new(fill)).unwrap();
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            assert_eq!(
+                tx.bucket(b"a")?.bucket(b"b")?.range::<[u8], _>(..).count(),
+                2000
+            );
+            assert!(tx.0.meta.borrow().pgid <= high_water + 2);
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bucket_sequence() {
+        let path = crate::test_db_path("bucket_sequence");
+        {
+            let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+            db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                let small = tx.create_bucket(b"small")?;
+                assert_eq!(small.sequence(), 0);
+                assert_eq!(small.next_sequence()?, 1);
+                let large = tx.create_bucket(b"large")?;
+                for i in 0..1000 {
+                    large.put(format!("{:04}", i).as_bytes(), b"v")?;
+                }
+                Ok(())
+            }))
+            .unwrap();
+            db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                assert_eq!(tx.bucket(b"small")?.next_sequence()?, 2);
+                tx.bucket(b"large")?.set_sequence(1000)?;
+                Ok(())
+            }))
+            .unwrap();
+            //回滚的事务不会修改 sequence
+            let _ = db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                tx.bucket(b"small")?.set_sequence(100)?;
+                Err(NKError::Unexpected("rollback".to_string()))
+            }));
+        }
+        let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            assert_eq!(tx.bucket(b"small")?.sequence(), 2);
+            assert_eq!(tx.bucket(b"large")?.sequence(), 1000);
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bucket_stats() {
+        let db = DB::open(&crate::test_db_path("bucket_stats"), DEFAULT_OPTIONS).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            let b = tx.create_bucket(b"default")?;
+            for i in 0..1000u32 {
+                b.put(format!("key{:04}", i).as_bytes(), &[0u8; 100])?;
+            }
+            b.create_bucket(b"inline")?.put(b"abc", b"123")?;
+            tx.create_bucket(b"empty")?;
+            Ok(())
+        })
+        .unwrap();
+        db.view(|tx| -> NKResult<()> {
+            let s = tx.bucket(b"default")?.stats()?;
+            assert_eq!(s.key_n, 1000 + 1 + 1);
+            assert_eq!(s.bucket_n, 2);
+            assert_eq!(s.inline_bucket_n, 1);
+            assert!(s.inline_bucket_inuse > 0);
+            assert_eq!(s.depth, 2);
+            assert_eq!(s.branch_page_n, 1);
+            assert!(s.leaf_page_n > 1);
+            assert_eq!(s.leaf_alloc, s.leaf_page_n * 4096);
+            assert!(s.leaf_inuse > 1000 * 107 && s.leaf_inuse < s.leaf_alloc);
+
+            let s = tx.bucket(b"empty")?.stats()?;
+            assert_eq!(s.key_n, 0);
+            assert_eq!(s.inline_bucket_n, 1);
+            assert_eq!(s.depth, 1);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bucket_comparator() {
+        let path = crate::test_db_path("bucket_comparator");
+        {
+            let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+            db.update(|tx| -> NKResult<()> {
+                let b = tx.create_bucket_with_comparator(b"numbers", BIG_ENDIAN)?;
+                for i in (0..3000u32).rev() {
+                    //去掉前导 0 的大端整数，长度不同
+                    let k = i.to_be_bytes();
+                    let k = &k[k.iter().position(|&x| x != 0).unwrap_or(3)..];
+                    b.put(k, &[0u8; 32])?;
+                }
+                let r = b.create_bucket_with_comparator(b"reverse", REVERSE)?;
+                for k in [b"a", b"c", b"b"] {
+                    r.put(k, b"v")?;
+                }
+                let names = tx.create_bucket_with_comparator(b"names", CASE_INSENSITIVE)?;
+                names.put(b"Alice", b"1")?;
+                names.put(b"ALICE", b"2")?;
+                Ok(())
+            })
+            .unwrap();
+        }
+        let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            assert!(matches!(
+                tx.bucket(b"numbers"),
+                Err(NKError::ErrComparatorMismatch(2))
+            ));
+            let b = tx.bucket_with_comparator(b"numbers", BIG_ENDIAN)?;
+            let keys: Vec<u32> = b
+                .range::<[u8], _>(..)
+                .map(|(k, _)| k.iter().fold(0, |n, &x| n << 8 | x as u32))
+                .collect();
+            assert_eq!(keys, (0..3000).collect::<Vec<_>>());
+            assert!(b.get(&[0, 0, 1, 0])?.is_some());
+
+            let r = b.bucket_with_comparator(b"reverse", REVERSE)?;
+            let keys: Vec<&[u8]> = r.range::<[u8], _>(..).map(|(k, _)| k).collect();
+            assert_eq!(keys, vec![b"c", b"b", b"a"]);
+
+            let names = tx.bucket_with_comparator(b"names", CASE_INSENSITIVE)?;
+            assert_eq!(names.get(b"alice")?.as_deref(), Some(&b"2"[..]));
+            assert_eq!(names.range::<[u8], _>(..).count(), 1);
+
+            assert!(matches!(
+                tx.bucket_ref(b"names"),
+                Err(NKError::ErrComparatorMismatch(3))
+            ));
+            let r = tx
+                .bucket_ref_with_comparator(b"numbers", BIG_ENDIAN)?
+                .bucket_ref_with_comparator(b"reverse", REVERSE)?;
+            assert_eq!(r.get(b"b")?.as_deref(), Some(&b"v"[..]));
+
+            //16 以下没有内置的 id 不能用
+            let reserved = Comparator {
+                id: 4,
+                compare: |a, b| a.cmp(b),
+            };
+            assert!(matches!(
+                tx.create_bucket_with_comparator(b"reserved", reserved),
+                Err(NKError::ErrComparatorReserved(4))
+            ));
+
+            tx.delete_bucket(b"numbers")?;
+            assert!(tx.check().is_empty());
+            Ok(())
+        })
+        .unwrap();
+        db.view(|tx| -> NKResult<()> {
+            assert!(tx.check().is_empty());
+            assert!(matches!(
+                tx.bucket_with_comparator(b"numbers", BIG_ENDIAN),
+                Err(NKError::ErrBucketNotFound)
+            ));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_delete_bucket_with_comparator() {
+        let db = DB::open(
+            &crate::test_db_path("delete_bucket_comparator"),
+            DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        db.update(|tx| -> NKResult<()> {
+            let r = tx
+                .create_bucket(b"parent")?
+                .create_bucket_with_comparator(b"reverse", REVERSE)?;
+            //子 bucket 的名字分布在多个按逆序排列的页里
+            for i in 0..300 {
+                let b = r.create_bucket(format!("child{:03}", i).as_bytes())?;
+                for j in 0..20 {
+                    b.put(format!("key{:02}", j).as_bytes(), &[0u8; 100])?;
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.view(|tx| -> NKResult<()> {
+            let s = tx.bucket(b"parent")?.stats()?;
+            assert_eq!(s.bucket_n, 1 + 1 + 300);
+            assert_eq!(s.key_n, 1 + 300 + 300 * 20);
+            Ok(())
+        })
+        .unwrap();
+        db.update(|tx| -> NKResult<()> {
+            tx.delete_bucket(b"parent")?;
+            Ok(())
+        })
+        .unwrap();
+        db.view(|tx| -> NKResult<()> {
+            assert!(tx.check().is_empty());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bucket_large_values() {
+        let path = crate::test_db_path("bucket_large_values");
+        let blob = |i: u8| -> Vec<u8> { (0..10 << 20).map(|j: u32| (j % 251) as u8 ^ i).collect() };
+        {
+            let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+            db.update(|tx| -> NKResult<()> {
+                let b = tx.create_bucket(b"blobs")?;
+                b.put(b"small", b"value")?;
+                for i in 0..3u8 {
+                    b.put(&[b'b', i], &blob(i))?;
+                }
+                Ok(())
+            })
+            .unwrap();
+            //覆盖一个大 value，旧的溢出页要整体释放
+            db.update(|tx| -> NKResult<()> {
+                tx.bucket(b"blobs")?.put(&[b'b', 1], &blob(7))?;
+                tx.bucket(b"blobs")?.delete(&[b'b', 2])?;
+                Ok(())
+            })
+            .unwrap();
+        }
+        let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+        db.view(|tx| -> NKResult<()> {
+            assert!(tx.check().is_empty());
+            let b = tx.bucket(b"blobs")?;
+            assert_eq!(b.get(b"small")?.as_deref(), Some(&b"value"[..]));
+            assert_eq!(b.get(&[b'b', 0])?.as_deref(), Some(blob(0).as_slice()));
+            assert_eq!(b.get(&[b'b', 1])?.as_deref(), Some(blob(7).as_slice()));
+            assert_eq!(b.get(&[b'b', 2])?, None);
+            //value 直接指向 mmap 中的溢出页，不做拷贝
+            let v = b.get(&[b'b', 0])?.unwrap().as_ptr() as usize;
+            let db = tx.0.db();
+            let mmap = db.mmap.read_recursive();
+            let data = mmap.data().as_ptr() as usize;
+            assert!(v > data && v < data + mmap.data().len());
+            Ok(())
+        })
+        .unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+        //释放的溢出页被后面的大 value 重新使用
+        db.update(|tx| -> NKResult<()> { tx.bucket(b"blobs")?.put(&[b'b', 3], &blob(3)) })
+            .unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+        db.view(|tx| -> NKResult<()> {
+            assert!(tx.check().is_empty());
+            assert_eq!(
+                tx.bucket(b"blobs")?.get(&[b'b', 3])?.as_deref(),
+                Some(blob(3).as_slice())
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bucket_compression() {
+        use crate::compression::Compression;
+        use crate::page::FeatureCompression;
+        use std::borrow::Cow;
+
+        let path = crate::test_db_path("bucket_compression");
+        let json = |i: u32| format!("{{\"id\":{},\"name\":\"nikidb\"}}", i).repeat(20);
+        let codecs = [Compression::Lz4, Compression::Zstd];
+        {
+            let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+            db.update(|tx| -> NKResult<()> {
+                for c in codecs {
+                    let b = tx.create_bucket(format!("{:?}", c).as_bytes())?;
+                    //开启压缩之前写入的 value 不压缩
+                    b.put(b"raw", json(0).as_bytes())?;
+                    if let Err(e) = b.set_compression(c) {
+                        assert!(
+                            matches!(e, NKError::ErrCompressionUnsupported(id) if id == c as u8)
+                        );
+                        continue;
+                    }
+                    for i in 0..100u32 {
+                        b.put(&i.to_be_bytes(), json(i).as_bytes())?;
+                    }
+                    b.put(b"short", b"value")?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        }
+        let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+        let enabled: Vec<_> = codecs.into_iter().filter(|c| c.check().is_ok()).collect();
+        assert_eq!(
+            db.0.meta().unwrap().flags & FeatureCompression != 0,
+            !enabled.is_empty()
+        );
+        db.view(|tx| -> NKResult<()> {
+            for c in enabled {
+                let b = tx.bucket_ref(format!("{:?}", c).as_bytes())?;
+                assert_eq!(b.compression(), c);
+                assert_eq!(b.get(b"raw")?.as_deref(), Some(json(0).as_bytes()));
+                assert_eq!(b.get(b"short")?.as_deref(), Some(&b"value"[..]));
+                //只有压缩过的 value 需要拷贝
+                assert!(matches!(b.get(b"raw")?, Some(Cow::Borrowed(_))));
+                assert!(matches!(b.get(&0u32.to_be_bytes())?, Some(Cow::Owned(_))));
+                let mut n = 0;
+                for (k, v) in b.range(0u32.to_be_bytes()..100u32.to_be_bytes()) {
+                    assert_eq!(
+                        v,
+                        json(u32::from_be_bytes(k.try_into().unwrap())).as_bytes()
+                    );
+                    n += 1;
+                }
+                assert_eq!(n, 100);
+                //叶子页里保存的是压缩后的数据
+                let stats = b.stats()?;
+                assert!(stats.leaf_inuse < 100 * json(0).len() / 4);
+            }
+            assert!(tx.check().is_empty());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bucket_seek_raw() {
+        use crate::page::CompressedLeafFlag;
+
+        let db = DB::open(&crate::test_db_path("bucket_seek_raw"), DEFAULT_OPTIONS).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            let b = tx.create_bucket(b"default")?;
+            //压缩方式不认识的 value，读的时候才报错
+            {
+                let mut c = b.cursor();
+                c.seek_raw(b"b")?;
+                c.node()?.put(b"b", b"b", &[9, 0], 0, CompressedLeafFlag);
+            }
+            assert!(matches!(
+                b.get(b"b"),
+                Err(NKError::ErrCompressionUnsupported(9))
+            ));
+            //创建和删除子 bucket 时不解压相邻的 value
+            b.create_bucket(b"a")?;
+            assert!(matches!(
+                b.delete_bucket(b"aa"),
+                Err(NKError::ErrBucketNotFound)
+            ));
+            b.delete_bucket(b"a")
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+use crate::bucket::{Bucket, MAX_FILL_PERCENT};
+use crate::comparator::Comparator;
+use crate::compression::Compression;
+use crate::db::{DB, DEFAULT_OPTIONS};
+use crate::error::{NKError, NKResult};
+use crate::page::FeaturePageChecksum;
+use crate::tx::Tx;
+use std::io;
+
+/// Copies every bucket and key of `src` into a new database file at
+/// `dst_path`, packing the pages as full as possible. Writes are committed
+/// every `tx_max_size` bytes of keys and values; zero commits once at the end.
+/// Buckets using a comparator that is not built in need
+/// `compact_with_comparators`; otherwise this fails with
+/// `NKError::ErrComparatorMismatch` before `dst_path` is created.
+pub fn compact(src: &DB, dst_path: &str, tx_max_size: u64) -> NKResult<()> {
+    compact_with_comparators(src, dst_path, tx_max_size, &[])
+}
+
+/// Like `compact`, also recreating buckets that use one of `comparators`.
+pub fn compact_with_comparators(
+    src: &DB,
+    dst_path: &str,
+    tx_max_size: u64,
+    comparators: &[Comparator],
+) -> NKResult<()> {
+    if std::path::Path::new(dst_path).exists() {
+        return Err(NKError::DBOpenFail(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", dst_path),
+        )));
+    }
+    let resolve = |id| {
+        comparators
+            .iter()
+            .find(|c| c.id == id)
+            .copied()
+            .or_else(|| Comparator::builtin(id))
+    };
+    //先确认所有子 bucket 的比较器都能还原，再创建新文件
+    src.view(|src_tx| -> NKResult<()> {
+        let root = unsafe { &mut *src_tx.0.root.as_ptr() };
+        walk_bucket(root, &resolve, &mut Vec::new(), &mut |_, _, _, _| Ok(()))
+    })?;
+    //新文件沿用源文件的页大小和页校验和，所有 bucket 的页都尽量填满。
+    //比较器、压缩这些格式特性在用到的时候会自己打上
+    let page_checksum = src.0.page_checksum() || src.0.meta()?.flags & FeaturePageChecksum != 0;
+    let options = DEFAULT_OPTIONS
+        .page_size(src.0.get_page_size())
+        .page_checksum(page_checksum)
+        .fill_percent(MAX_FILL_PERCENT);
+    let dst = DB::open(dst_path, options)?;
+    let mut tx = dst.begin(true)?;
+    let mut size = 0;
+    src.view(|src_tx| -> NKResult<()> {
+        let root = unsafe { &mut *src_tx.0.root.as_ptr() };
+        walk_bucket(root, &resolve, &mut Vec::new(), &mut |path, k, v, child| {
+            //事务写满了先提交，保证内存占用不随数据量增长
+            let sz = (k.len() + v.len()) as u64;
+            if tx_max_size != 0 && size + sz > tx_max_size {
+                tx.commit()?;
+                tx = dst.begin(true)?;
+                size = 0;
+            }
+            size += sz;
+
+            let Some(child) = child else {
+                return bucket_by_path(&mut tx, path)?.put(k, v);
+            };
+            let b = if path.is_empty() {
+                tx.create_bucket_with_comparator(k, child.comparator)?
+            } else {
+                bucket_by_path(&mut tx, path)?.create_bucket_with_comparator(k, child.comparator)?
+            };
+            b.set_compression(child.compression)?;
+            b.set_sequence(child.sequence)
+        })
+    })?;
+    tx.commit()
+}
+
+type Path = [(Vec<u8>, Comparator)];
+
+//子 bucket 需要在新文件里还原的属性
+struct Child {
+    comparator: Comparator,
+    compression: Compression,
+    sequence: u64,
+}
+
+fn bucket_by_path<'a>(tx: &'a mut Tx, path: &Path) -> NKResult<&'a mut Bucket> {
+    let mut b = tx.bucket_with_comparator(&path[0].0, path[0].1)?;
+    for (k, cmp) in &path[1..] {
+        b = b.bucket_with_comparator(k, *cmp)?;
+    }
+    Ok(b)
+}
+
+//深度优先遍历所有的 key，子 bucket 带上比较器、压缩方式和 sequence
+fn walk_bucket<R, F>(
+    b: &mut Bucket,
+    resolve: &R,
+    path: &mut Vec<(Vec<u8>, Comparator)>,
+    f: &mut F,
+) -> NKResult<()>
+where
+    R: Fn(u16) -> Option<Comparator>,
+    F: FnMut(&Path, &[u8], &[u8], Option<Child>) -> NKResult<()>,
+{
+    let bp = b as *mut Bucket;
+    let mut c = b.cursor();
+    let mut item = c.first()?;
+    while let Some(k) = item.key() {
+        if item.is_bucket() {
+            let id = Comparator::id_from_flags(item.flags());
+            let cmp = resolve(id).ok_or(NKError::ErrComparatorMismatch(id))?;
+            let child = unsafe { &mut *bp }.bucket_with_comparator(k, cmp)?;
+            let attrs = Child {
+                comparator: cmp,
+                compression: child.compression(),
+                sequence: child.sequence(),
+            };
+            f(path, k, &[], Some(attrs))?;
+            path.push((k.to_vec(), cmp));
+            walk_bucket(child, resolve, path, f)?;
+            path.pop();
+        } else {
+            f(path, k, item.value().unwrap_or_default(), None)?;
+        }
+        item = c.next()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact() {
+        let src_path = crate::test_db_path("compact_src");
+        let src = DB::open(&src_path, DEFAULT_OPTIONS.page_checksum(true)).unwrap();
+        src.update(|tx| -> NKResult<()> {
+            let b = tx.create_bucket(b"default")?;
+            b.set_sequence(42)?;
+            for i in 0..2000u32 {
+                b.put(format!("key{:04}", i).as_bytes(), &[1u8; 100])?;
+            }
+            let nested = b.create_bucket(b"nested")?;
+            nested.set_sequence(7)?;
+            nested.create_bucket(b"inline")?.put(b"abc", b"123")?;
+            for i in 0..500u32 {
+                nested.put(format!("key{:04}", i).as_bytes(), b"value")?;
+            }
+            tx.create_bucket(b"empty")?;
+            Ok(())
+        })
+        .unwrap();
+        //删掉大部分数据，空闲页留在文件里
+        src.update(|tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            for i in 100..2000u32 {
+                b.delete(format!("key{:04}", i).as_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let dst_path = crate::test_db_path("compact_dst");
+        compact(&src, &dst_path, 4096).unwrap();
+        assert!(compact(&src, &dst_path, 4096).is_err());
+        let src_size = std::fs::metadata(&src_path).unwrap().len();
+        let dst_size = std::fs::metadata(&dst_path).unwrap().len();
+        assert!(dst_size < src_size);
+
+        let dst = DB::open(&dst_path, DEFAULT_OPTIONS).unwrap();
+        //新文件也带页校验和
+        assert_ne!(dst.0.meta().unwrap().flags & FeaturePageChecksum, 0);
+        dst.view(|tx| -> NKResult<()> {
+            assert!(tx.check().is_empty());
+            tx.bucket(b"empty")?;
+            let b = tx.bucket(b"default")?;
+            assert_eq!(b.sequence(), 42);
+            for i in 0..2000u32 {
+                let v = b.get(format!("key{:04}", i).as_bytes())?;
+                assert_eq!(v.is_some(), i < 100);
+            }
+            let nested = b.bucket(b"nested")?;
+            assert_eq!(nested.sequence(), 7);
+            assert_eq!(nested.get(b"key0499")?.as_deref(), Some(&b"value"[..]));
+            assert_eq!(
+                nested.bucket(b"inline")?.get(b"abc")?.as_deref(),
+                Some(&b"123"[..])
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_compact_custom_comparator() {
+        //按长度排序，长度相同再按字节排序
+        const BY_LEN: Comparator = Comparator {
+            id: 16,
+            compare: |a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+        };
+        let src_path = crate::test_db_path("compact_cmp_src");
+        let src = DB::open(&src_path, DEFAULT_OPTIONS).unwrap();
+        src.update(|tx| -> NKResult<()> {
+            tx.create_bucket(b"default")?.put(b"key", b"value")?;
+            let b = tx.create_bucket(b"parent")?;
+            let custom = b.create_bucket_with_comparator(b"by_len", BY_LEN)?;
+            for k in ["ccc", "a", "bb"] {
+                custom.put(k.as_bytes(), b"value")?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let dst_path = crate::test_db_path("compact_cmp_dst");
+        assert!(matches!(
+            compact(&src, &dst_path, 0),
+            Err(NKError::ErrComparatorMismatch(16))
+        ));
+        assert!(!std::path::Path::new(&dst_path).exists());
+
+        compact_with_comparators(&src, &dst_path, 0, &[BY_LEN]).unwrap();
+        let dst = DB::open(&dst_path, DEFAULT_OPTIONS).unwrap();
+        dst.view(|tx| -> NKResult<()> {
+            assert!(tx.bucket(b"default")?.get(b"key")?.is_some());
+            let b = tx
+                .bucket(b"parent")?
+                .bucket_with_comparator(b"by_len", BY_LEN)?;
+            let keys: Vec<&[u8]> = b.range::<[u8], _>(..).map(|(k, _)| k).collect();
+            assert_eq!(keys, [&b"a"[..], b"bb", b"ccc"]);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+use crate::page::BucketLeafFlag;
+use std::cmp::Ordering;
+
+/// Orders the keys of a bucket. The `id` is stored with the bucket, and opening
+/// the bucket with a comparator of another id fails with
+/// `NKError::ErrComparatorMismatch`. Ids below 16 are reserved for the built-in
+/// comparators, and creating a bucket with any other id in that range fails with
+/// `NKError::ErrComparatorReserved`.
+#[derive(Clone, Copy)]
+pub struct Comparator {
+    pub id: u16,
+    pub compare: fn(&[u8], &[u8]) -> Ordering,
+}
+
+/// Byte-wise order, used by buckets created without a comparator.
+pub const BYTEWISE: Comparator = Comparator {
+    id: 0,
+    compare: |a, b| a.cmp(b),
+};
+
+/// Reverse byte-wise order.
+pub const REVERSE: Comparator = Comparator {
+    id: 1,
+    compare: |a, b| b.cmp(a),
+};
+
+/// Keys are big-endian unsigned integers of any length.
+pub const BIG_ENDIAN: Comparator = Comparator {
+    id: 2,
+    compare: |a, b| {
+        let a = &a[a.iter().position(|&x| x != 0).unwrap_or(a.len())..];
+        let b = &b[b.iter().position(|&x| x != 0).unwrap_or(b.len())..];
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    },
+};
+
+/// Byte-wise order ignoring ASCII case.
+pub const CASE_INSENSITIVE: Comparator = Comparator {
+    id: 3,
+    compare: |a, b| {
+        a.iter()
+            .map(u8::to_ascii_lowercase)
+            .cmp(b.iter().map(u8::to_ascii_lowercase))
+    },
+};
+
+impl Comparator {
+    pub(crate) fn builtin(id: u16) -> Option<Comparator> {
+        match id {
+            0 => Some(BYTEWISE),
+            1 => Some(REVERSE),
+            2 => Some(BIG_ENDIAN),
+            3 => Some(CASE_INSENSITIVE),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn reserved(id: u16) -> bool {
+        id < 16 && Self::builtin(id).is_none()
+    }
+
+    //比较器 id 保存在 bucket 所在叶子元素 flags 的高 16 位
+    pub(crate) fn id_from_flags(flags: u32) -> u16 {
+        (flags >> 16) as u16
+    }
+
+    pub(crate) fn bucket_flags(&self) -> u32 {
+        BucketLeafFlag | (self.id as u32) << 16
+    }
+
+    pub(crate) fn eq(&self, a: &[u8], b: &[u8]) -> bool {
+        (self.compare)(a, b) == Ordering::Equal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_comparators() {
+        assert_eq!((REVERSE.compare)(b"a", b"b"), Ordering::Greater);
+        assert_eq!((BIG_ENDIAN.compare)(&[0, 2], &[1]), Ordering::Greater);
+        assert_eq!((BIG_ENDIAN.compare)(&[1, 0], &[0xFF]), Ordering::Greater);
+        assert_eq!((BIG_ENDIAN.compare)(&[0, 0, 7], &[7]), Ordering::Equal);
+        assert_eq!((CASE_INSENSITIVE.compare)(b"ABC", b"abd"), Ordering::Less);
+        assert!(CASE_INSENSITIVE.eq(b"Key", b"kEY"));
+        assert_eq!(Comparator::id_from_flags(REVERSE.bucket_flags()), 1);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::error::{NKError, NKResult};
+
+/// Values shorter than this are never compressed.
+pub const MIN_COMPRESS_SIZE: usize = 64;
+
+/// How a bucket compresses the values put into it, see
+/// `Bucket::set_compression`. Each algorithm needs the cargo feature of the
+/// same name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+impl Compression {
+    pub(crate) fn from_id(id: u8) -> NKResult<Compression> {
+        match id {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            _ => Err(NKError::ErrCompressionUnsupported(id)),
+        }
+    }
+
+    //压缩方式保存在 bucket 所在叶子元素 flags 的 8~15 位，见 Bucket::set_compression
+    pub(crate) fn from_flags(flags: u32) -> NKResult<Compression> {
+        Self::from_id((flags >> 8) as u8)
+    }
+
+    pub(crate) fn bucket_flags(&self) -> u32 {
+        (*self as u32) << 8
+    }
+
+    pub(crate) fn check(&self) -> NKResult<()> {
+        match self {
+            Compression::None => Ok(()),
+            Compression::Lz4 if cfg!(feature = "lz4") => Ok(()),
+            Compression::Zstd if cfg!(feature = "zstd") => Ok(()),
+            _ => Err(NKError::ErrCompressionUnsupported(*self as u8)),
+        }
+    }
+
+    //压缩后的 value 第一个字节是压缩方式，换了压缩方式之后旧的 value 也能解压。
+    //太短或者压缩后没有变小的 value 返回 None，按原样保存
+    pub(crate) fn compress(&self, value: &[u8]) -> NKResult<Option<Vec<u8>>> {
+        if *self == Compression::None || value.len() < MIN_COMPRESS_SIZE {
+            return Ok(None);
+        }
+        let mut buf = vec![*self as u8];
+        buf.extend(self.encode(value)?);
+        Ok(Some(buf).filter(|b| b.len() < value.len()))
+    }
+
+    pub(crate) fn decompress(value: &[u8]) -> NKResult<Vec<u8>> {
+        let (&id, data) = value
+            .split_first()
+            .ok_or_else(|| NKError::ErrCompression("empty value".to_string()))?;
+        Self::from_id(id)?.decode(data)
+    }
+
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    fn encode(&self, value: &[u8]) -> NKResult<Vec<u8>> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(value)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(value, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .map_err(|e| NKError::ErrCompression(e.to_string())),
+            _ => Err(NKError::ErrCompressionUnsupported(*self as u8)),
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    fn decode(&self, data: &[u8]) -> NKResult<Vec<u8>> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| NKError::ErrCompression(e.to_string())),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                zstd::stream::decode_all(data).map_err(|e| NKError::ErrCompression(e.to_string()))
+            }
+            _ => Err(NKError::ErrCompressionUnsupported(*self as u8)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression() {
+        let value = b"{\"name\":\"nikidb\",\"kind\":\"kv\"}".repeat(20);
+        assert_eq!(Compression::None.compress(&value).unwrap(), None);
+        assert_eq!(
+            Compression::from_flags(Compression::Zstd.bucket_flags()).unwrap(),
+            Compression::Zstd
+        );
+        assert!(matches!(
+            Compression::decompress(&[9, 0]),
+            Err(NKError::ErrCompressionUnsupported(9))
+        ));
+        for (c, enabled) in [
+            (Compression::Lz4, cfg!(feature = "lz4")),
+            (Compression::Zstd, cfg!(feature = "zstd")),
+        ] {
+            assert_eq!(c.check().is_ok(), enabled);
+            if !enabled {
+                //没有打开 feature 时压缩和解压都要报错，不能把原始数据当成压缩结果
+                let id = c as u8;
+                assert!(matches!(
+                    c.compress(&value),
+                    Err(NKError::ErrCompressionUnsupported(i)) if i == id
+                ));
+                assert!(matches!(
+                    Compression::decompress(&[id, 0]),
+                    Err(NKError::ErrCompressionUnsupported(i)) if i == id
+                ));
+                continue;
+            }
+            //太短的 value 不压缩
+            assert_eq!(c.compress(b"short").unwrap(), None);
+            let compressed = c.compress(&value).unwrap().unwrap();
+            assert!(compressed.len() < value.len() / 4);
+            assert_eq!(Compression::decompress(&compressed).unwrap(), value);
+        }
+    }
+}
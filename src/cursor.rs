@@ -1,11 +1,18 @@
 use crate::bucket::{Bucket, PageNode};
-use crate::error::NKResult;
+use crate::compression::Compression;
+use crate::error::{NKError, NKResult};
 use crate::node::Node;
-use crate::page::{BucketLeafFlag, LeafPageFlag, Page, Pgid};
+use crate::page::{BucketLeafFlag, CompressedLeafFlag, LeafPageFlag, Page, Pgid};
+use crate::wal::Op;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
 use std::rc::Rc;
 use std::str;
-pub(crate) struct Cursor<'a> {
-    pub(crate) bucket: &'a mut Bucket,
+/// A cursor walks the key/value pairs of a bucket in key order. The items it
+/// returns borrow the cursor, since `delete` frees the deleted key and value.
+pub struct Cursor<'a> {
+    pub(crate) bucket: &'a Bucket,
     stack: Vec<ElemRef>,
 }
 
@@ -15,29 +22,49 @@ pub(crate) struct ElemRef {
     index: usize, //寻找 key 在哪个 element
 }
 
-pub(crate) struct Item<'a>(
-    pub(crate) Option<&'a [u8]>,
+/// A key/value pair returned by a cursor. Both are `None` when the cursor
+/// is past either end of the bucket. The value borrows the page it is stored
+/// in, unless it was stored compressed.
+pub struct Item<'a>(
     pub(crate) Option<&'a [u8]>,
+    pub(crate) Option<Cow<'a, [u8]>>,
     pub(crate) u32,
 );
 
 impl<'a> Item<'a> {
     fn from(key: &'a [u8], value: &'a [u8], flags: u32) -> Item<'a> {
-        Self(Some(key), Some(value), flags)
+        Self(Some(key), Some(Cow::Borrowed(value)), flags)
     }
 
     fn null() -> Item<'a> {
         Self(None, None, 0)
     }
 
-    pub(crate) fn key(&self) -> Option<&'a [u8]> {
+    fn strip_bucket(mut self) -> Item<'a> {
+        if self.2 & BucketLeafFlag != 0 {
+            self.1 = None;
+        }
+        self
+    }
+
+    pub fn key(&self) -> Option<&'a [u8]> {
         self.0
     }
 
-    pub(crate) fn value(&self) -> Option<&'a [u8]> {
+    /// The value is `None` for sub-bucket entries.
+    pub fn value(&self) -> Option<&[u8]> {
+        self.1.as_deref()
+    }
+
+    /// Like `value`, keeping the borrow of the page when possible.
+    pub fn into_value(self) -> Option<Cow<'a, [u8]>> {
         self.1
     }
 
+    pub fn is_bucket(&self) -> bool {
+        self.2 & BucketLeafFlag != 0
+    }
+
     pub(crate) fn flags(&self) -> u32 {
         self.2
     }
@@ -47,14 +74,14 @@ impl ElemRef {
     fn is_leaf(&self) -> bool {
         match &self.page_node {
             PageNode::Node(n) => n.node().is_leaf,
-            PageNode::Page(p) => self.get_page(p).flags == LeafPageFlag,
+            PageNode::Page(p) => self.get_page(p).flags & LeafPageFlag != 0,
         }
     }
 
     fn count(&self) -> usize {
         match &self.page_node {
             PageNode::Node(n) => n.node().inodes.len(),
-            PageNode::Page(p) => self.get_page(p).count as usize,
+            PageNode::Page(p) => self.get_page(p).count(),
         }
     }
 
@@ -75,90 +102,215 @@ impl ElemRef {
             PageNode::Page(_) => None,
         }
     }
+
+    //当前 element 指向的子页面
+    fn pgid(&self) -> NKResult<Pgid> {
+        match &self.page_node {
+            PageNode::Node(n) => Ok(n.node().inodes.get(self.index).ok_or("get node fail")?.pgid),
+            PageNode::Page(p) => Ok(self.get_page(p).branch_page_element(self.index).pgid),
+        }
+    }
 }
 
 impl<'a> Cursor<'a> {
-    pub(crate) fn new(bucket: &'a mut Bucket) -> Cursor<'a> {
+    pub(crate) fn new(bucket: &'a Bucket) -> Cursor<'a> {
         Self {
             bucket: bucket,
             stack: Vec::new(),
         }
     }
 
-    fn first(&mut self) -> NKResult<()> {
+    /// Moves the cursor to the first item in the bucket and returns it.
+    /// The returned key is `None` if the bucket is empty.
+    pub fn first(&mut self) -> NKResult<Item<'_>> {
+        let item = self.first_item()?;
+        self.output(item)
+    }
+
+    fn first_item(&mut self) -> NKResult<Item<'a>> {
+        self.stack.clear();
+        let page_node = self.bucket.page_node(self.bucket.ibucket.root)?;
+        self.stack.push(ElemRef {
+            page_node,
+            index: 0,
+        });
+        self.first_leaf()?;
+        if self.stack.last().ok_or("stack empty")?.count() == 0 {
+            return self.next_item();
+        }
+        self.key_value()
+    }
+
+    /// Moves the cursor to the last item in the bucket and returns it.
+    /// The returned key is `None` if the bucket is empty.
+    pub fn last(&mut self) -> NKResult<Item<'_>> {
+        let item = self.last_item()?;
+        self.output(item)
+    }
+
+    fn last_item(&mut self) -> NKResult<Item<'a>> {
+        self.stack.clear();
+        let page_node = self.bucket.page_node(self.bucket.ibucket.root)?;
+        let mut elem_ref = ElemRef {
+            page_node,
+            index: 0,
+        };
+        elem_ref.index = elem_ref.count().saturating_sub(1);
+        self.stack.push(elem_ref);
+        self.last_leaf()?;
+        if self.stack.last().ok_or("stack empty")?.count() == 0 {
+            return self.prev_item();
+        }
+        self.key_value()
+    }
+
+    /// Moves the cursor to the next item in the bucket and returns it.
+    /// The returned key is `None` once the cursor is past the last item.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> NKResult<Item<'_>> {
+        let item = self.next_item()?;
+        self.output(item)
+    }
+
+    /// Moves the cursor to the previous item in the bucket and returns it.
+    /// The returned key is `None` once the cursor is before the first item.
+    pub fn prev(&mut self) -> NKResult<Item<'_>> {
+        let item = self.prev_item()?;
+        self.output(item)
+    }
+
+    /// Moves the cursor to the given key, or to the next key if it does not exist.
+    /// Sub-bucket entries are returned with a `None` value.
+    pub fn seek(&mut self, key: &[u8]) -> NKResult<Item<'_>> {
+        let item = self.seek_raw(key)?;
+        self.output(item)
+    }
+
+    //和 seek 一样定位，但返回叶子元素里原始的 value
+    pub(crate) fn seek_raw(&mut self, key: &[u8]) -> NKResult<Item<'a>> {
+        let item = self.seek_item(key)?;
+        let ref_elem = self.stack.last().ok_or("stack empty")?;
+        if ref_elem.index >= ref_elem.count() {
+            return self.next_item();
+        }
+        Ok(item)
+    }
+
+    //返回给调用方的 item 去掉子 bucket 的 value，压缩过的 value 解压后返回
+    pub(crate) fn output(&self, item: Item<'a>) -> NKResult<Item<'a>> {
+        let mut item = item.strip_bucket();
+        if let (Some(v), true) = (&item.1, item.2 & CompressedLeafFlag != 0) {
+            let v = Compression::decompress(v)?;
+            item.1 = Some(Cow::Owned(v));
+        }
+        Ok(item)
+    }
+
+    //从栈顶一直向下走到最左边的叶子节点
+    fn first_leaf(&mut self) -> NKResult<()> {
         loop {
             let ref_elem = self.stack.last().ok_or("stack empty")?;
             if ref_elem.is_leaf() {
                 break;
             }
-            let pgid = match &ref_elem.page_node {
-                PageNode::Node(n) => {
-                    n.node()
-                        .inodes
-                        .get(ref_elem.index)
-                        .ok_or("get node fail")?
-                        .pgid
-                }
-                PageNode::Page(p) => {
-                    ref_elem
-                        .get_page(p)
-                        .branch_page_element(ref_elem.index)
-                        .pgid
-                }
-            };
-            let page_node = self.bucket.page_node(pgid)?;
+            let page_node = self.bucket.page_node(ref_elem.pgid()?)?;
             self.stack.push(ElemRef {
-                page_node: page_node,
+                page_node,
                 index: 0,
             });
         }
         Ok(())
     }
 
-    fn last(&mut self) {}
+    //从栈顶一直向下走到最右边的叶子节点
+    fn last_leaf(&mut self) -> NKResult<()> {
+        loop {
+            let ref_elem = self.stack.last().ok_or("stack empty")?;
+            if ref_elem.is_leaf() {
+                break;
+            }
+            let page_node = self.bucket.page_node(ref_elem.pgid()?)?;
+            let mut elem_ref = ElemRef {
+                page_node,
+                index: 0,
+            };
+            elem_ref.index = elem_ref.count().saturating_sub(1);
+            self.stack.push(elem_ref);
+        }
+        Ok(())
+    }
 
-    fn next(&mut self) -> NKResult<Item<'a>> {
+    fn next_item(&mut self) -> NKResult<Item<'a>> {
         loop {
-            let mut index: usize = 0;
-            let mut i: i32 = -1;
-            for _i in (0..self.stack.len() - 1).rev() {
-                //取上一页数据
-                let elem = self.stack.get_mut(_i).ok_or("get elem fail")?;
-                if elem.index < elem.count() {
+            //找到还有下一个元素的那一层
+            let mut i = self.stack.len();
+            while i > 0 {
+                let elem = &mut self.stack[i - 1];
+                if elem.index + 1 < elem.count() {
                     elem.index += 1;
-                    i = _i as i32;
                     break;
                 }
+                i -= 1;
             }
-            if i == -1 {
+            if i == 0 {
                 return Ok(Item::null());
             }
-            self.stack.truncate((i + 1) as usize);
-            self.first()?;
-            if self.stack.last().unwrap().count() == 0 {
+            self.stack.truncate(i);
+            self.first_leaf()?;
+            //跳过空的叶子节点
+            if self.stack.last().ok_or("stack empty")?.count() == 0 {
                 continue;
             }
             return self.key_value();
         }
     }
 
-    fn prev(&mut self) {}
-
-    fn delete(&mut self) {}
+    fn prev_item(&mut self) -> NKResult<Item<'a>> {
+        loop {
+            //找到还有上一个元素的那一层
+            while let Some(elem) = self.stack.last_mut() {
+                if elem.index > 0 {
+                    elem.index -= 1;
+                    break;
+                }
+                self.stack.pop();
+            }
+            if self.stack.is_empty() {
+                return Ok(Item::null());
+            }
+            self.last_leaf()?;
+            //跳过空的叶子节点
+            if self.stack.last().ok_or("stack empty")?.count() == 0 {
+                continue;
+            }
+            return self.key_value();
+        }
+    }
 
-    pub(crate) fn seek(&mut self, key: &[u8]) -> NKResult<Item<'a>> {
-        //  let mut item: Option<Item> = None;
-        let mut item = self.seek_item(key)?;
-        let ref_elem = self.stack.last().ok_or("stack empty")?;
-        if ref_elem.index >= ref_elem.count() {
-            item = self.next()?;
+    /// Deletes the item under the cursor and moves the cursor to the item that
+    /// followed it, which is returned. Call `prev` afterwards to keep scanning
+    /// backwards. Sub-bucket entries can not be deleted this way.
+    pub fn delete(&mut self) -> NKResult<Item<'_>> {
+        self.bucket.check_writable()?;
+        let item = self.key_value()?;
+        let key = item
+            .key()
+            .ok_or("cursor not positioned on an item")?
+            .to_vec();
+        if item.is_bucket() {
+            return Err(NKError::IncompatibleValue);
         }
-        if item.key().is_none() {
-            return Ok(Item::null());
-        } else if (item.flags() & BucketLeafFlag) != 0 {
-            item.1 = None;
+        let mut n = self.node()?;
+        n.del(&key);
+        self.bucket.log(|path| Op::Delete { path, key });
+        //叶子节点已经实例化，下一个元素移到了被删除元素的位置，超出叶子节点时再往上走
+        let elem = self.stack.last_mut().ok_or("stack empty")?;
+        elem.page_node = PageNode::Node(n);
+        if elem.index < elem.count() {
+            return self.output(self.key_value()?);
         }
-        Ok(item)
+        let item = self.next_item()?;
+        self.output(item)
     }
 
     pub(crate) fn seek_item(&mut self, key: &[u8]) -> NKResult<Item<'a>> {
@@ -173,6 +325,9 @@ impl<'a> Cursor<'a> {
 
     fn key_value(&self) -> NKResult<Item<'a>> {
         let ref_elem = self.stack.last().ok_or("stack empty")?;
+        if ref_elem.index >= ref_elem.count() {
+            return Ok(Item::null());
+        }
         unsafe {
             match &ref_elem.page_node {
                 PageNode::Node(n) => {
@@ -219,13 +374,14 @@ impl<'a> Cursor<'a> {
 
     //搜索叶子节点的数据
     fn nsearch(&mut self, key: &[u8]) -> NKResult<()> {
+        let compare = self.bucket.comparator.compare;
         let e = self.stack.last_mut().ok_or("stack empty")?;
         match &e.page_node {
             PageNode::Node(n) => {
                 let index = match n
                     .node()
                     .inodes
-                    .binary_search_by(|inode| inode.key.as_slice().cmp(key))
+                    .binary_search_by(|inode| compare(&inode.key, key))
                 {
                     Ok(v) => (v),
                     Err(e) => (e),
@@ -234,7 +390,7 @@ impl<'a> Cursor<'a> {
             }
             PageNode::Page(p) => {
                 let inodes = e.get_page(p).leaf_page_elements();
-                let index = match inodes.binary_search_by(|inode| inode.key().cmp(key)) {
+                let index = match inodes.binary_search_by(|inode| compare(inode.key(), key)) {
                     Ok(v) => (v),
                     Err(e) => (e),
                 };
@@ -245,8 +401,9 @@ impl<'a> Cursor<'a> {
     }
 
     fn search_page(&mut self, key: &[u8], p: &Page) -> NKResult<()> {
+        let compare = self.bucket.comparator.compare;
         let inodes = p.branch_page_elements();
-        let (exact, mut index) = match inodes.binary_search_by(|inode| inode.key().cmp(key)) {
+        let (exact, mut index) = match inodes.binary_search_by(|inode| compare(inode.key(), key)) {
             Ok(v) => (true, v),
             Err(e) => (false, e),
         };
@@ -259,10 +416,11 @@ impl<'a> Cursor<'a> {
     }
 
     fn search_node(&mut self, key: &[u8], n: &Node) -> NKResult<()> {
+        let compare = self.bucket.comparator.compare;
         let (exact, mut index) = match n
             .node()
             .inodes
-            .binary_search_by(|inode| inode.key.as_slice().cmp(key))
+            .binary_search_by(|inode| compare(&inode.key, key))
         {
             Ok(v) => (true, v),
             Err(e) => (false, e),
@@ -283,14 +441,418 @@ impl<'a> Cursor<'a> {
         let mut elem = self.stack.first().unwrap();
         let mut n = match &elem.page_node {
             PageNode::Node(n) => n.clone(),
-            PageNode::Page(p) => self.bucket.node(elem.get_page(p).id, None),
+            PageNode::Page(p) => self.bucket.node(elem.get_page(p).id, None)?,
         };
 
         for e in self.stack[..self.stack.len() - 1].iter() {
-            let child = n.child_at(self.bucket, e.index, Some(Rc::downgrade(&n.0)));
+            let child = n.child_at(self.bucket, e.index, Some(Rc::downgrade(&n.0)))?;
             n = child;
         }
         assert!(n.node().is_leaf, "expected leaf node");
         Ok(n)
     }
 }
+
+/// An iterator over the key/value pairs of a bucket whose keys fall in a range.
+/// Sub-bucket entries are skipped. The iteration stops at the first error,
+/// e.g. a page failing its checksum, which is then returned by `error`.
+pub struct Range<'a> {
+    cursor: Cursor<'a>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    reverse: bool,
+    started: bool,
+    done: bool,
+    error: Option<NKError>,
+}
+
+fn to_owned_bound<K: AsRef<[u8]> + ?Sized>(b: Bound<&K>) -> Bound<Vec<u8>> {
+    match b {
+        Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+        Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+//大于所有以 prefix 开头的 key 的最小 key
+fn prefix_end(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xFF {
+            end.push(last + 1);
+            return Bound::Excluded(end);
+        }
+    }
+    Bound::Unbounded
+}
+
+impl<'a> Range<'a> {
+    pub(crate) fn new<K, R>(cursor: Cursor<'a>, range: R, reverse: bool) -> Range<'a>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        Self {
+            cursor,
+            start: to_owned_bound(range.start_bound()),
+            end: to_owned_bound(range.end_bound()),
+            reverse,
+            started: false,
+            done: false,
+            error: None,
+        }
+    }
+
+    pub(crate) fn prefix(cursor: Cursor<'a>, prefix: &[u8], reverse: bool) -> Range<'a> {
+        Self {
+            cursor,
+            start: Bound::Included(prefix.to_vec()),
+            end: prefix_end(prefix),
+            reverse,
+            started: false,
+            done: false,
+            error: None,
+        }
+    }
+
+    //第一次迭代时把游标放到范围的起点（反向迭代时是终点）。Range 不删除元素，
+    //返回的 item 可以借用整个 bucket
+    fn seek_start(&mut self) -> NKResult<Item<'a>> {
+        if !self.reverse {
+            return match &self.start {
+                Bound::Unbounded => self.cursor.first_item(),
+                Bound::Included(k) => self.cursor.seek_raw(k),
+                Bound::Excluded(k) => {
+                    let item = self.cursor.seek_raw(k)?;
+                    if item
+                        .key()
+                        .is_some_and(|key| self.cursor.bucket.comparator.eq(key, k))
+                    {
+                        return self.cursor.next_item();
+                    }
+                    Ok(item)
+                }
+            };
+        }
+        let (key, inclusive) = match &self.end {
+            Bound::Unbounded => return self.cursor.last_item(),
+            Bound::Included(k) => (k, true),
+            Bound::Excluded(k) => (k, false),
+        };
+        let compare = self.cursor.bucket.comparator.compare;
+        let item = self.cursor.seek_raw(key)?;
+        match item.key().map(|k| compare(k, key)) {
+            None => self.cursor.last_item(),
+            Some(Ordering::Greater) => self.cursor.prev_item(),
+            Some(Ordering::Equal) if !inclusive => self.cursor.prev_item(),
+            Some(_) => Ok(item),
+        }
+    }
+
+    /// Returns the error that ended the iteration, if any.
+    pub fn error(&self) -> Option<&NKError> {
+        self.error.as_ref()
+    }
+
+    fn in_range(&self, key: &[u8]) -> bool {
+        let compare = self.cursor.bucket.comparator.compare;
+        if self.reverse {
+            match &self.start {
+                Bound::Unbounded => true,
+                Bound::Included(k) => compare(key, k) != Ordering::Less,
+                Bound::Excluded(k) => compare(key, k) == Ordering::Greater,
+            }
+        } else {
+            match &self.end {
+                Bound::Unbounded => true,
+                Bound::Included(k) => compare(key, k) != Ordering::Greater,
+                Bound::Excluded(k) => compare(key, k) == Ordering::Less,
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Range<'a> {
+    type Item = (&'a [u8], Cow<'a, [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let item = if !self.started {
+                self.started = true;
+                self.seek_start()
+            } else if self.reverse {
+                self.cursor.prev_item()
+            } else {
+                self.cursor.next_item()
+            };
+            let item = match item {
+                Ok(item) => item,
+                Err(e) => {
+                    self.done = true;
+                    self.error = Some(e);
+                    return None;
+                }
+            };
+            let key = match item.key() {
+                Some(k) if self.in_range(k) => k,
+                _ => {
+                    self.done = true;
+                    return None;
+                }
+            };
+            if item.is_bucket() {
+                continue;
+            }
+            match self.cursor.output(item) {
+                Ok(item) => return Some((key, item.into_value().unwrap_or_default())),
+                Err(e) => {
+                    self.done = true;
+                    self.error = Some(e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DB, DEFAULT_OPTIONS};
+    use crate::error::NKResult;
+    use crate::tx::Tx;
+    use std::borrow::Cow;
+    use std::ops::Bound;
+    use std::str;
+
+    fn open_with_keys(name: &str, n: usize) -> DB {
+        let db = DB::open(&crate::test_db_path(name), DEFAULT_OPTIONS).unwrap();
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            tx.create_bucket(b"default")?;
+            Ok(())
+        }))
+        .unwrap();
+        for chunk in (0..n).collect::<Vec<usize>>().chunks(100) {
+            db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                let b = tx.bucket(b"default")?;
+                for i in chunk {
+                    b.put(
+                        format!("key{:05}", i).as_bytes(),
+                        format!("{}", i).as_bytes(),
+                    )?;
+                }
+                Ok(())
+            }))
+            .unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_cursor_first_next() {
+        let db = open_with_keys("cursor_first_next", 1000);
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let mut c = tx.bucket(b"default")?.cursor();
+            let mut item = c.first()?;
+            let mut i = 0;
+            while let Some(k) = item.key() {
+                assert_eq!(k, format!("key{:05}", i).as_bytes());
+                assert_eq!(item.value().unwrap(), format!("{}", i).as_bytes());
+                i += 1;
+                item = c.next()?;
+            }
+            assert_eq!(i, 1000);
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cursor_last_prev() {
+        let db = open_with_keys("cursor_last_prev", 1000);
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let mut c = tx.bucket(b"default")?.cursor();
+            let mut item = c.last()?;
+            let mut i = 1000;
+            while let Some(k) = item.key() {
+                i -= 1;
+                assert_eq!(k, format!("key{:05}", i).as_bytes());
+                item = c.prev()?;
+            }
+            assert_eq!(i, 0);
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cursor_seek() {
+        let db = open_with_keys("cursor_seek", 500);
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            b.create_bucket(b"key00100x")?;
+            let mut c = b.cursor();
+            let item = c.seek(b"key00250")?;
+            assert_eq!(item.key(), Some(&b"key00250"[..]));
+            let item = c.seek(b"key00100a")?;
+            assert_eq!(item.key(), Some(&b"key00100x"[..]));
+            assert!(item.is_bucket() && item.value().is_none());
+            assert_eq!(c.next()?.key(), Some(&b"key00101"[..]));
+            assert_eq!(c.prev()?.key(), Some(&b"key00100x"[..]));
+            assert!(c.seek(b"zzz")?.key().is_none());
+            assert!(b.get(b"key00100a")?.is_none());
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cursor_empty_bucket() {
+        let db = open_with_keys("cursor_empty", 0);
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let mut c = tx.bucket(b"default")?.cursor();
+            assert!(c.first()?.key().is_none());
+            assert!(c.last()?.key().is_none());
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    fn keys<'a>(r: impl Iterator<Item = (&'a [u8], Cow<'a, [u8]>)>) -> Vec<String> {
+        r.map(|(k, _)| String::from_utf8(k.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_bucket_range() {
+        let db = open_with_keys("bucket_range", 300);
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            let r = keys(b.range(&b"key00010"[..]..&b"key00013"[..]));
+            assert_eq!(r, vec!["key00010", "key00011", "key00012"]);
+            let r = keys(b.range(&b"key00010"[..]..=&b"key00013"[..]));
+            assert_eq!(r.len(), 4);
+            let r = keys(b.range::<[u8], _>((
+                Bound::Excluded(&b"key00010"[..]),
+                Bound::Included(&b"key00012"[..]),
+            )));
+            assert_eq!(r, vec!["key00011", "key00012"]);
+            assert_eq!(keys(b.range(&b"key00297"[..]..)).len(), 3);
+            assert_eq!(keys(b.range(..&b"key00003"[..])).len(), 3);
+            assert_eq!(keys(b.range::<[u8], _>(..)).len(), 300);
+
+            let r = keys(b.range_rev(&b"key00010"[..]..&b"key00013"[..]));
+            assert_eq!(r, vec!["key00012", "key00011", "key00010"]);
+            let r = keys(b.range_rev(&b"key00010a"[..]..=&b"key00012a"[..]));
+            assert_eq!(r, vec!["key00012", "key00011"]);
+            let r = keys(b.range_rev(&b"key00298"[..]..&b"zzz"[..]));
+            assert_eq!(r, vec!["key00299", "key00298"]);
+            assert_eq!(keys(b.range_rev::<[u8], _>(..)).len(), 300);
+            assert!(keys(b.range(&b"zzz"[..]..)).is_empty());
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bucket_prefix() {
+        let db = DB::open(&crate::test_db_path("bucket_prefix"), DEFAULT_OPTIONS).unwrap();
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let b = tx.create_bucket(b"users")?;
+            for id in 1..=3 {
+                for field in ["age", "mail", "name"] {
+                    b.put(format!("user/{}/{}", id, field).as_bytes(), b"v")?;
+                }
+            }
+            b.put(b"user0", b"v")?;
+            b.put(&[b'x', 0xFF, 0xFF], b"v")?;
+            b.create_bucket(b"user/2/index")?;
+            Ok(())
+        }))
+        .unwrap();
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let b = tx.bucket(b"users")?;
+            let r = keys(b.prefix(b"user/2/"));
+            assert_eq!(r, vec!["user/2/age", "user/2/mail", "user/2/name"]);
+            let r = keys(b.prefix_rev(b"user/2/"));
+            assert_eq!(r, vec!["user/2/name", "user/2/mail", "user/2/age"]);
+            assert_eq!(b.prefix(b"user/").count(), 9);
+            assert_eq!(b.prefix(&[b'x', 0xFF]).count(), 1);
+            assert_eq!(b.prefix_rev(&[b'x', 0xFF]).count(), 1);
+            assert_eq!(b.prefix(b"nobody").count(), 0);
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cursor_delete() {
+        let db = open_with_keys("cursor_delete", 1000);
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            b.create_bucket(b"key00500x")?;
+            let mut c = b.cursor();
+            //正向删除所有偶数 key
+            let mut item = c.first()?;
+            while let Some(k) = item.key() {
+                let i: usize = str::from_utf8(&k[3..8]).unwrap().parse().unwrap();
+                if i % 2 == 0 && !item.is_bucket() {
+                    item = c.delete()?;
+                } else {
+                    item = c.next()?;
+                }
+            }
+            assert!(c.seek(b"key00500x")?.is_bucket());
+            assert!(c.delete().is_err());
+            //反向删除所有 3 的倍数
+            let mut item = c.last()?;
+            while let Some(k) = item.key() {
+                let i: usize = str::from_utf8(&k[3..8]).unwrap().parse().unwrap();
+                if i % 3 == 0 && !item.is_bucket() {
+                    c.delete()?;
+                }
+                item = c.prev()?;
+            }
+            Ok(())
+        }))
+        .unwrap();
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            let want: Vec<String> = (0..1000)
+                .filter(|i| i % 2 != 0 && i % 3 != 0)
+                .map(|i| format!("key{:05}", i))
+                .collect();
+            assert_eq!(keys(b.range::<[u8], _>(..)), want);
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cursor_delete_all() {
+        let db = open_with_keys("cursor_delete_all", 1000);
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let mut c = tx.bucket(b"default")?.cursor();
+            let mut item = c.first()?;
+            let mut n = 0;
+            while item.key().is_some() {
+                item = c.delete()?;
+                n += 1;
+                if n < 1000 {
+                    assert_eq!(item.key(), Some(format!("key{:05}", n).as_bytes()));
+                }
+            }
+            assert_eq!(n, 1000);
+            assert!(c.first()?.key().is_none());
+            Ok(())
+        }))
+        .unwrap();
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            assert!(tx.bucket(b"default")?.cursor().first()?.key().is_none());
+            Ok(())
+        }))
+        .unwrap();
+    }
+}
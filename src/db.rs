@@ -1,97 +1,222 @@
-use crate::bucket::IBucket;
+use crate::batch::{Batch, BatchFn, Call};
+use crate::bucket::{IBucket, DEFAULT_FILL_PERCENT, MAX_FILL_PERCENT, MIN_FILL_PERCENT};
 use crate::error::{NKError, NKResult};
 use crate::freelist::FreeList;
 use crate::node::NodeImpl;
-use crate::page::{FreeListPageFlag, LeafPageFlag, Meta, MetaPageFlag, OwnerPage, Page, Pgid};
+use crate::page::{
+    FeaturePageChecksum, FreeListPageFlag, LeafPageFlag, Meta, MetaPageFlag, MetaSize, OwnerPage,
+    Page, PageChecksumSize, Pgid,
+};
 use crate::tx::{Tx, TxImpl, Txid};
+use crate::wal::{self, Wal, WalPages};
 use crate::{magic, version};
 use lock_api::{RawMutex, RawRwLock};
 use parking_lot::{Mutex, RwLock};
-use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::fs::{File, OpenOptions, TryLockError};
 use std::os::unix::prelude::FileExt;
 use std::ptr::null;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-const MAX_MAP_SIZE: u64 = 0x0FFF_FFFF; //256TB
+const MAX_MAP_SIZE: u64 = 0xFFFF_FFFF_FFFF; //256TB
 
 const MAX_MMAP_STEP: u64 = 1 << 30;
 
+const MIN_PAGE_SIZE: usize = 1024;
+
+const MAX_PAGE_SIZE: usize = 64 * 1024;
+
+/// Prefaults the whole mmap on open, see `Options::mmap_flags`.
+pub const MAP_POPULATE: u32 = 0x8000;
+
 fn get_page_size() -> usize {
     page_size::get()
     // return 256;
 }
 
 #[derive(Clone)]
-pub struct DB(Arc<DBImpl>);
+pub struct DB(pub(crate) Arc<DBImpl>);
 
 impl DB {
-    fn begin_rwtx(&self) -> Tx {
+    fn begin_rwtx(&self) -> NKResult<Tx> {
         unsafe {
             self.0.rw_lock.raw().lock();
         }
-        let mut tx = Tx(Arc::new(TxImpl::build(true, self.0.clone())));
+        let tx = TxImpl::build(true, self.0.clone()).map_err(|e| {
+            unsafe {
+                self.0.rw_lock.raw().unlock();
+            }
+            e
+        })?;
+        let mut tx = Tx(Arc::new(tx));
         tx.init();
-        *(self.0.rwtx.try_write().unwrap()) = Some(tx.clone());
+        *(self.0.rwtx.try_write().unwrap()) = Some(tx.0.clone());
         let txs = self.0.txs.read();
         let minid = txs
             .iter()
-            .map(|tx| tx.id())
+            .map(|tx| tx.meta.borrow().txid)
             .min()
             .unwrap_or(0xFFFF_FFFF_FFFF_FFFF);
-        if minid > 0 {
-            self.0.freelist.try_write().unwrap().release(minid - 1);
+        //WAL 模式下检查点之后释放的页还被数据文件里的 meta 引用，检查点之前不能重用
+        let mut release = minid.saturating_sub(1);
+        if self.0.wal.is_some() {
+            let checkpoint = self.0.mmap.read_recursive().meta().map_or(0, |m| m.txid);
+            release = release.min(checkpoint + 1);
+        }
+        if release > 0 {
+            self.0.freelist.try_write().unwrap().release(release);
         }
         drop(txs);
-        tx
+        Ok(tx)
     }
 
-    fn begin_tx(&self) -> Tx {
+    fn begin_tx(&self) -> NKResult<Tx> {
         unsafe {
             self.0.mmap.raw().lock_shared();
         }
-        let mut tx = Tx(Arc::new(TxImpl::build(false, self.0.clone())));
+        let tx = TxImpl::build(false, self.0.clone()).map_err(|e| {
+            unsafe {
+                self.0.mmap.raw().unlock_shared();
+            }
+            e
+        })?;
+        let mut tx = Tx(Arc::new(tx));
         tx.init();
-        self.0.txs.try_write().unwrap().push(tx.clone());
-        tx
+        self.0.txs.try_write().unwrap().push(tx.0.clone());
+        self.0.stats.lock().tx_n += 1;
+        Ok(tx)
     }
 
-    fn begin(&self, writable: bool) -> Tx {
-        if writable {
-            self.begin_rwtx()
-        } else {
-            self.begin_tx()
+    /// Starts a new transaction. Only one writable transaction can be open at a
+    /// time, so `begin(true)` blocks until the previous one is committed or
+    /// rolled back. The transaction is rolled back when it is dropped without
+    /// calling `commit` or `rollback`.
+    pub fn begin(&self, writable: bool) -> NKResult<Tx> {
+        if !writable {
+            return self.begin_tx();
         }
+        if self.0.read_only() {
+            return Err(NKError::ErrDatabaseReadOnly);
+        }
+        self.begin_rwtx()
     }
 
     pub fn open(db_path: &str, options: Options) -> NKResult<DB> {
         DBImpl::open(db_path, options)
     }
 
-    pub fn update<'a>(
-        &self,
-        mut handler: Box<dyn FnMut(&mut Tx) -> NKResult<()> + 'a>,
-    ) -> NKResult<()> {
-        let mut t = self.begin(true);
-        if let Err(e) = handler(&mut t) {
-            t.rollback()?;
-            return Err(e);
+    /// Runs `f` in a writable transaction. The transaction is committed if
+    /// `f` returns `Ok` and rolled back otherwise.
+    pub fn update<F, R, E>(&self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Tx) -> Result<R, E>,
+        E: From<NKError>,
+    {
+        let mut t = self.begin(true)?;
+        match f(&mut t) {
+            Ok(r) => {
+                t.commit()?;
+                Ok(r)
+            }
+            Err(e) => {
+                t.rollback()?;
+                Err(e)
+            }
         }
-        t.commit()?;
-        Ok(())
     }
 
-    pub fn view<'a>(
-        &self,
-        mut handler: Box<dyn FnMut(&mut Tx) -> NKResult<()> + 'a>,
-    ) -> NKResult<()> {
-        let mut t = self.begin(false);
-        if let Err(e) = handler(&mut t) {
-            t.rollback()?;
-            return Err(e);
-        }
+    /// Runs `f` in a read-only transaction.
+    pub fn view<F, R, E>(&self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Tx) -> Result<R, E>,
+        E: From<NKError>,
+    {
+        let mut t = self.begin(false)?;
+        let r = f(&mut t);
         t.rollback()?;
-        Ok(())
+        r
+    }
+
+    /// Runs `f` in a writable transaction shared with other concurrent `batch`
+    /// calls, so they are committed together. `f` may be called more than once:
+    /// if the combined transaction fails, each call is retried on its own.
+    /// Only useful when several threads call it at the same time.
+    pub fn batch<F>(&self, f: F) -> NKResult<()>
+    where
+        F: Fn(&mut Tx) -> NKResult<()> + Send + Sync + 'static,
+    {
+        let f: BatchFn = Arc::new(f);
+        let (done, rx) = mpsc::channel();
+        {
+            let mut batch = self.0.batch.lock();
+            let b = match &*batch {
+                Some(b) => b.clone(),
+                None => {
+                    let b = Arc::new(Batch::new());
+                    let (db, timer) = (self.clone(), b.clone());
+                    let delay = self.0.options.max_batch_delay;
+                    thread::spawn(move || {
+                        thread::sleep(delay);
+                        timer.trigger(&db);
+                    });
+                    batch.replace(b.clone());
+                    b
+                }
+            };
+            let mut calls = b.calls.lock();
+            calls.push(Call { f: f.clone(), done });
+            //批次满了立即提交
+            if calls.len() >= self.0.options.max_batch_size {
+                batch.take();
+                let db = self.clone();
+                let b = b.clone();
+                thread::spawn(move || b.trigger(&db));
+            }
+        }
+        //批次失败或者执行批次的线程 panic 时单独执行
+        match rx.recv() {
+            Ok(true) => Ok(()),
+            _ => self.update(|tx| f(tx)),
+        }
+    }
+
+    /// In WAL mode, writes the pages of the commits since the last checkpoint
+    /// to the database file, then the meta page of the last one, and empties
+    /// the log. Runs periodically in the background, see `Options::wal`.
+    pub fn checkpoint(&self) -> NKResult<()> {
+        self.0.checkpoint()
+    }
+
+    /// Rewrites a damaged meta page from the other, valid one. Returns whether
+    /// a page was repaired. `DB::open` succeeds as long as one meta page is
+    /// valid, so this can be called right after opening.
+    pub fn repair_meta(&self) -> NKResult<bool> {
+        if self.0.read_only() {
+            return Err(NKError::ErrDatabaseReadOnly);
+        }
+        unsafe {
+            self.0.rw_lock.raw().lock();
+        }
+        let res = self.0.repair_meta();
+        unsafe {
+            self.0.rw_lock.raw().unlock();
+        }
+        res
+    }
+
+    /// Returns the current statistics of the database.
+    pub fn stats(&self) -> Stats {
+        let mut stats = *self.0.stats.lock();
+        let page_size = self.0.get_page_size();
+        let freelist = self.0.freelist.read();
+        stats.free_page_n = freelist.ids.len();
+        stats.pending_page_n = freelist.pending.values().map(|ids| ids.len()).sum();
+        stats.free_alloc = (stats.free_page_n + stats.pending_page_n) * page_size;
+        stats.freelist_inuse = freelist.size();
+        stats.open_tx_n = self.0.txs.read().len();
+        stats
     }
 
     fn print(&self) {
@@ -99,14 +224,43 @@ impl DB {
     }
 }
 
+/// Statistics of the database, see `DB::stats`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Number of free pages on the freelist.
+    pub free_page_n: usize,
+    /// Number of pages freed but still in use by open read transactions.
+    pub pending_page_n: usize,
+    /// Bytes allocated in free and pending pages.
+    pub free_alloc: usize,
+    /// Bytes used by the freelist page.
+    pub freelist_inuse: usize,
+    /// Total number of read transactions started.
+    pub tx_n: usize,
+    /// Number of read transactions currently open.
+    pub open_tx_n: usize,
+    /// Number of committed write transactions.
+    pub commit_n: usize,
+    /// Time spent rebalancing and spilling nodes into pages.
+    pub spill_time: Duration,
+    /// Time spent writing pages to the file.
+    pub write_time: Duration,
+    /// Time spent syncing the file.
+    pub sync_time: Duration,
+}
+
 pub(crate) struct DBImpl {
+    options: Options,
     file: RwLock<File>,
     pub(crate) mmap: RwLock<MmapUtil>,
     page_pool: RwLock<Vec<Vec<u8>>>,
     pub(crate) freelist: RwLock<FreeList>,
-    rwtx: RwLock<Option<Tx>>,
-    txs: RwLock<Vec<Tx>>,
+    pub(crate) rwtx: RwLock<Option<Arc<TxImpl>>>,
+    txs: RwLock<Vec<Arc<TxImpl>>>,
     pub(crate) rw_lock: Mutex<()>,
+    pub(crate) batch: Mutex<Option<Arc<Batch>>>,
+    pub(crate) stats: Mutex<Stats>,
+    pub(crate) wal: Option<Mutex<Wal>>,
 }
 
 pub(crate) struct MmapUtil {
@@ -115,6 +269,8 @@ pub(crate) struct MmapUtil {
     meta0: *const Meta,
     meta1: *const Meta,
     db_size: u64,
+    //每页一位，记录开启校验和时已经校验过的页，重新映射后清空
+    checked: Vec<AtomicU64>,
 }
 
 unsafe impl Send for MmapUtil {}
@@ -128,6 +284,7 @@ impl Default for MmapUtil {
             meta0: null(),
             meta1: null(),
             db_size: 0,
+            checked: Vec::new(),
         }
     }
 }
@@ -144,7 +301,7 @@ impl MmapUtil {
         }
         let remainder = size % MAX_MMAP_STEP;
         if remainder > 0 {
-            size += MAX_MAP_SIZE - remainder;
+            size += MAX_MMAP_STEP - remainder;
         };
         let page_size = self.page_size as u64;
         if (size % page_size) != 0 {
@@ -156,10 +313,16 @@ impl MmapUtil {
         Ok(size)
     }
 
-    pub(crate) fn set_mmap(&mut self, file: &File, mut min_size: u64) -> NKResult<()> {
+    pub(crate) fn set_mmap(
+        &mut self,
+        file: &File,
+        mut min_size: u64,
+        mmap_flags: u32,
+    ) -> NKResult<()> {
         let mut mmap_opts = memmap::MmapOptions::new();
 
-        let mut size = file.metadata().map_err(|e| NKError::DBOpenFail(e))?.len();
+        let file_size = file.metadata().map_err(|e| NKError::DBOpenFail(e))?.len();
+        let mut size = file_size;
         if size < min_size {
             size = min_size;
         }
@@ -172,32 +335,49 @@ impl MmapUtil {
                 .map(file)
                 .map_err(|e| format!("mmap failed: {}", e))?
         };
+        if mmap_flags & MAP_POPULATE != 0 {
+            //只能访问文件范围内的页，超出文件大小会触发 SIGBUS
+            populate(&nmmap, file_size as usize);
+        }
+        //只要有一个 meta 页是好的就能打开，另一个可以用 DB::repair_meta 修复
         let meta0 = self.page_in_buffer(&nmmap, 0).meta();
         let meta1 = self.page_in_buffer(&nmmap, 1).meta();
-        meta0.validate()?;
-        meta1.validate()?;
+        if let (Err(e), Err(_)) = (meta0.validate(), meta1.validate()) {
+            return Err(e);
+        }
         self.meta0 = meta0;
         self.meta1 = meta1;
         self.mmap.replace(nmmap);
         self.db_size = min_size as u64;
+        let words = (min_size / self.page_size as u64).div_ceil(64);
+        self.checked = (0..words).map(|_| AtomicU64::new(0)).collect();
         Ok(())
     }
 
-    pub(crate) fn meta(&self) -> Meta {
-        unsafe {
-            let mut metaA = self.meta0;
-            let mut metaB = self.meta1;
-            if (*self.meta1).txid > (*self.meta0).txid {
-                metaA = self.meta1;
-                metaB = self.meta0;
-            }
-            if (*metaA).validate().is_ok() {
-                return *metaA.clone();
-            }
-            if (*metaB).validate().is_ok() {
-                return *metaB.clone();
-            }
-            panic!("niki.DB.meta(): invalid meta pages")
+    fn is_checked(&self, id: Pgid) -> bool {
+        self.checked
+            .get(id as usize / 64)
+            .is_some_and(|w| w.load(Ordering::Relaxed) & (1 << (id % 64)) != 0)
+    }
+
+    fn set_checked(&self, id: Pgid) {
+        if let Some(w) = self.checked.get(id as usize / 64) {
+            w.fetch_or(1 << (id % 64), Ordering::Relaxed);
+        }
+    }
+
+    //返回两个 meta 页里事务 id 较大且有效的那个
+    pub(crate) fn meta(&self) -> NKResult<Meta> {
+        let (meta0, meta1) = unsafe { (*self.meta0, *self.meta1) };
+        let (a, b) = if meta1.txid > meta0.txid {
+            (meta1, meta0)
+        } else {
+            (meta0, meta1)
+        };
+        match (a.validate(), b.validate()) {
+            (Ok(()), _) => Ok(a),
+            (_, Ok(())) => Ok(b),
+            (Err(e), _) => Err(e),
         }
     }
 
@@ -212,73 +392,341 @@ impl MmapUtil {
     pub(crate) fn page(&self, id: Pgid) -> *const Page {
         self.page_in_buffer(&self.mmap.as_ref().unwrap(), id as u32)
     }
+
+    #[cfg(test)]
+    pub(crate) fn data(&self) -> &[u8] {
+        self.mmap.as_ref().unwrap()
+    }
 }
 
 #[derive(Clone, Copy)]
 pub struct Options {
     no_grow_sync: bool,
 
+    no_sync: bool,
+
     read_only: bool,
 
     mmap_flags: u32,
 
     initial_mmap_size: u64,
+
+    page_size: usize,
+
+    fill_percent: f64,
+
+    max_batch_size: usize,
+
+    max_batch_delay: Duration,
+
+    timeout: Duration,
+
+    wal: bool,
+
+    checkpoint_interval: Duration,
+
+    page_checksum: bool,
 }
 
 pub static DEFAULT_OPTIONS: Options = Options {
     no_grow_sync: false,
+    no_sync: false,
     read_only: false,
     mmap_flags: 0,
     initial_mmap_size: 0,
+    page_size: 0,
+    fill_percent: DEFAULT_FILL_PERCENT,
+    max_batch_size: 1000,
+    max_batch_delay: Duration::from_millis(10),
+    timeout: Duration::from_secs(0),
+    wal: false,
+    checkpoint_interval: Duration::from_secs(1),
+    page_checksum: false,
 };
 
+impl Default for Options {
+    fn default() -> Self {
+        DEFAULT_OPTIONS
+    }
+}
+
+impl Options {
+    /// Skips fdatasync after each commit. Faster, but committed transactions
+    /// can be lost if the machine crashes.
+    pub fn no_sync(mut self, no_sync: bool) -> Options {
+        self.no_sync = no_sync;
+        self
+    }
+
+    /// Skips truncating and syncing the file when the database grows.
+    pub fn no_grow_sync(mut self, no_grow_sync: bool) -> Options {
+        self.no_grow_sync = no_grow_sync;
+        self
+    }
+
+    /// Opens the database read-only with a shared file lock, so several
+    /// processes can read the same file at once.
+    pub fn read_only(mut self, read_only: bool) -> Options {
+        self.read_only = read_only;
+        self
+    }
+
+    /// How long `DB::open` waits for the file lock held by another process.
+    /// Zero waits forever.
+    pub fn timeout(mut self, timeout: Duration) -> Options {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Page size used when a new database file is created. Zero uses the OS
+    /// page size. Existing files keep the page size they were created with.
+    pub fn page_size(mut self, page_size: usize) -> Options {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Initial size of the mmap, so readers don't block the writer while the
+    /// file grows up to this size.
+    pub fn initial_mmap_size(mut self, initial_mmap_size: u64) -> Options {
+        self.initial_mmap_size = initial_mmap_size;
+        self
+    }
+
+    /// Flags for the mmap. Only `MAP_POPULATE` is supported.
+    pub fn mmap_flags(mut self, mmap_flags: u32) -> Options {
+        self.mmap_flags = mmap_flags;
+        self
+    }
+
+    /// Default fill percent of the buckets, between 0.1 and 1.0.
+    pub fn fill_percent(mut self, fill_percent: f64) -> Options {
+        self.fill_percent = fill_percent;
+        self
+    }
+
+    /// Maximum number of calls committed together by `DB::batch`.
+    pub fn max_batch_size(mut self, max_batch_size: usize) -> Options {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// How long `DB::batch` waits for other calls before committing.
+    pub fn max_batch_delay(mut self, max_batch_delay: Duration) -> Options {
+        self.max_batch_delay = max_batch_delay;
+        self
+    }
+
+    /// Commits append the changed keys and values to a write-ahead log next to
+    /// the database file and only sync the log. Their pages are kept in memory
+    /// until a background checkpoint writes them to the file, together with
+    /// the meta page of the last commit, and empties the log. A log left by a
+    /// crash is replayed on `DB::open`, also when the database is opened
+    /// without this option.
+    /// The log only knows the built-in comparators: creating or modifying a
+    /// bucket that uses a custom comparator fails with `ErrWalComparator`.
+    pub fn wal(mut self, wal: bool) -> Options {
+        self.wal = wal;
+        self
+    }
+
+    /// How often the background checkpoint runs in WAL mode.
+    pub fn checkpoint_interval(mut self, checkpoint_interval: Duration) -> Options {
+        self.checkpoint_interval = checkpoint_interval;
+        self
+    }
+
+    /// Writes a checksum at the end of every leaf, branch and freelist page
+    /// and verifies it the first time a page is read. A mismatch fails with
+    /// `NKError::ErrPageChecksum`. Pages written without the option are not
+    /// checked.
+    pub fn page_checksum(mut self, page_checksum: bool) -> Options {
+        self.page_checksum = page_checksum;
+        self
+    }
+
+    fn validate(&self) -> NKResult<()> {
+        if self.page_size != 0
+            && (!self.page_size.is_power_of_two()
+                || self.page_size < MIN_PAGE_SIZE
+                || self.page_size > MAX_PAGE_SIZE)
+        {
+            return Err(NKError::ErrInvalidOption(format!(
+                "page size {} must be a power of two between {} and {}",
+                self.page_size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+            )));
+        }
+        if self.initial_mmap_size > MAX_MAP_SIZE {
+            return Err(NKError::ErrInvalidOption(format!(
+                "initial mmap size {} is larger than {}",
+                self.initial_mmap_size, MAX_MAP_SIZE
+            )));
+        }
+        if self.mmap_flags & !MAP_POPULATE != 0 {
+            return Err(NKError::ErrInvalidOption(format!(
+                "unsupported mmap flags {:#x}",
+                self.mmap_flags
+            )));
+        }
+        if !(MIN_FILL_PERCENT..=MAX_FILL_PERCENT).contains(&self.fill_percent) {
+            return Err(NKError::ErrInvalidOption(format!(
+                "fill percent {} must be between {} and {}",
+                self.fill_percent, MIN_FILL_PERCENT, MAX_FILL_PERCENT
+            )));
+        }
+        if self.max_batch_size == 0 {
+            return Err(NKError::ErrInvalidOption(
+                "max batch size must be greater than 0".to_string(),
+            ));
+        }
+        if self.wal && self.read_only {
+            return Err(NKError::ErrInvalidOption(
+                "wal can not be used in read only mode".to_string(),
+            ));
+        }
+        if self.wal && self.checkpoint_interval.is_zero() {
+            return Err(NKError::ErrInvalidOption(
+                "checkpoint interval must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+//memmap 不支持 MAP_POPULATE，逐页读一次让内核提前把文件读进页缓存
+fn populate(mmap: &memmap::Mmap, len: usize) {
+    let step = get_page_size();
+    let mut i = 0;
+    while i < len {
+        unsafe { std::ptr::read_volatile(mmap.as_ptr().add(i)) };
+        i += step;
+    }
+}
+
+//第一个 meta 页损坏时按可能的页大小去找第二个 meta 页
+pub(crate) fn read_page_size(file: &File) -> NKResult<usize> {
+    let mut buf = vec![0u8; Page::header_size() + MetaSize];
+    let mut read_meta = |offset: u64| -> NKResult<Meta> {
+        file.read_exact_at(&mut buf, offset)
+            .map_err(|_e| ("can't read to file", _e))?;
+        let m = *Page::from_buf(&buf).meta();
+        m.validate().map(|_| m)
+    };
+    let err = match read_meta(0) {
+        Ok(m) => return Ok(m.page_size),
+        Err(e) => e,
+    };
+    let mut page_size = MIN_PAGE_SIZE;
+    while page_size <= MAX_PAGE_SIZE {
+        if let Ok(m) = read_meta(page_size as u64) {
+            if m.page_size == page_size {
+                return Ok(page_size);
+            }
+        }
+        page_size *= 2;
+    }
+    Err(err)
+}
+
+//写进程持有排他锁，只读进程持有共享锁，防止多个进程同时写同一个文件
+pub(crate) fn flock(file: &File, exclusive: bool, timeout: Duration) -> NKResult<()> {
+    let start = Instant::now();
+    loop {
+        let res = if exclusive {
+            file.try_lock()
+        } else {
+            file.try_lock_shared()
+        };
+        match res {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => return Err(NKError::DBOpenFail(e)),
+        }
+        if !timeout.is_zero() && start.elapsed() > timeout {
+            return Err(NKError::ErrTimeout);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
 impl DBImpl {
     pub fn open(db_path: &str, options: Options) -> NKResult<DB> {
+        options.validate()?;
+        //只读模式下以 O_RDONLY 打开文件，不会创建文件
         let f = OpenOptions::new()
             .read(true)
-            .write(true)
-            .create(true)
+            .write(!options.read_only)
+            .create(!options.read_only)
             .open(db_path)
             .map_err(|e| NKError::DBOpenFail(e))?;
+        flock(&f, !options.read_only, options.timeout)?;
         let size = f.metadata().map_err(|e| NKError::DBOpenFail(e))?.len();
-        let mut db = Self::new(f);
+        let mut db = Self::new(f, options);
         if size == 0 {
+            if options.read_only {
+                return Err(NKError::ErrInvalid);
+            }
             db.init()?;
         } else {
-            let mut buf = vec![0; get_page_size()];
-            db.file
-                .try_read()
-                .unwrap()
-                .read_at(&mut buf, 0)
-                .map_err(|_e| ("can't read to file", _e))?;
-            let m = db.mmap.try_read().unwrap().page_in_buffer(&buf, 0).meta();
-            m.validate()?;
-            db.mmap.try_write().unwrap().page_size = m.page_size;
+            db.mmap.try_write().unwrap().page_size = read_page_size(&db.file.try_read().unwrap())?;
         }
-        db.mmap
+        db.mmap.try_write().unwrap().set_mmap(
+            &db.file.try_read().unwrap(),
+            options.initial_mmap_size,
+            options.mmap_flags,
+        )?;
+        let meta = db.mmap.try_read().unwrap().meta()?;
+        if meta.version < version && !options.read_only {
+            return Err(NKError::ErrUpgradeRequired);
+        }
+        let freelist = meta.freelist;
+        db.freelist
             .try_write()
             .unwrap()
-            .set_mmap(&db.file.try_read().unwrap(), options.initial_mmap_size)?;
-        db.freelist.try_write().unwrap().read(unsafe {
-            &*db.mmap
-                .try_read()
-                .unwrap()
-                .page(db.mmap.try_read().unwrap().meta().freelist)
+            .read(unsafe { &*db.page(freelist)? });
+        //不开 WAL 时也要先重放上次留下的日志，否则已经提交的事务会丢失
+        let wal_path = format!("{}-wal", db_path);
+        if !options.wal {
+            if std::fs::metadata(&wal_path).map_or(true, |m| m.len() == 0) {
+                return Ok(DB(Arc::new(db)));
+            } else if options.read_only {
+                return Err(NKError::ErrWalReplayRequired);
+            }
+            let (mut w, records) = Wal::open(&wal_path)?;
+            let db = DB(Arc::new(db));
+            wal::replay(&db, records)?;
+            w.reset()?;
+            return Ok(db);
+        }
+        let (w, records) = Wal::open(&wal_path)?;
+        db.wal = Some(Mutex::new(w));
+        let db = DB(Arc::new(db));
+        wal::replay(&db, records)?;
+        //后台定期做检查点，db 关闭后线程退出
+        let weak = Arc::downgrade(&db.0);
+        thread::spawn(move || loop {
+            thread::sleep(options.checkpoint_interval);
+            match weak.upgrade() {
+                Some(db) => {
+                    let _ = db.checkpoint();
+                }
+                None => return,
+            }
         });
-        Ok(DB(Arc::new(db)))
+        Ok(db)
     }
 
     fn print(&self) {
-        let meta = self.meta();
+        let meta = self.meta().unwrap();
         let root = meta.root.root;
-        let p = unsafe { &*self.page(root) };
+        let p = unsafe { &*self.page(root).unwrap() };
         let mut node = NodeImpl::new().build();
         node.read(p);
         node.print(self);
     }
 
-    fn new(file: File) -> DBImpl {
+    fn new(file: File, options: Options) -> DBImpl {
         Self {
+            options,
             file: RwLock::new(file),
             mmap: RwLock::new(MmapUtil::default()),
             page_pool: RwLock::new(Vec::new()),
@@ -286,11 +734,18 @@ impl DBImpl {
             rwtx: RwLock::new(None),
             txs: RwLock::new(Vec::new()),
             rw_lock: Mutex::new(()),
+            batch: Mutex::new(None),
+            stats: Mutex::new(Stats::default()),
+            wal: None,
         }
     }
 
     fn init(&mut self) -> NKResult<()> {
-        let page_size = get_page_size();
+        let page_size = if self.options.page_size == 0 {
+            get_page_size()
+        } else {
+            self.options.page_size
+        };
         self.mmap.try_write().unwrap().page_size = page_size;
         let mut buf: Vec<u8> = vec![0; 4 * page_size];
         for i in 0..2 {
@@ -310,6 +765,9 @@ impl DBImpl {
             m.root = IBucket::new(3);
             m.pgid = 4;
             m.txid = i as Txid;
+            if self.options.page_checksum {
+                m.flags |= FeaturePageChecksum;
+            }
             m.checksum = m.sum64();
         }
 
@@ -331,6 +789,12 @@ impl DBImpl {
         p.flags = LeafPageFlag;
         p.count = 0;
 
+        if self.options.page_checksum {
+            for i in 2..4 {
+                Page::set_checksum(&mut buf[i * page_size..(i + 1) * page_size]);
+            }
+        }
+
         self.write_at(&buf, 0)?;
         self.sync()?;
 
@@ -338,20 +802,43 @@ impl DBImpl {
     }
 
     pub(crate) fn write_at(&self, buf: &[u8], pos: u64) -> NKResult<()> {
+        let start = Instant::now();
         self.file
             .try_write()
             .unwrap()
             .write_at(buf, pos)
             .map_err(|_e| ("can't write to file", _e))?;
+        self.stats.lock().write_time += start.elapsed();
         Ok(())
     }
 
     pub(crate) fn sync(&self) -> NKResult<()> {
+        if self.options.no_sync {
+            return Ok(());
+        }
+        let start = Instant::now();
         self.file
-            .try_write()
+            .try_read()
             .unwrap()
-            .flush()
-            .map_err(|_e| ("can't flush file", _e))?;
+            .sync_data()
+            .map_err(|_e| ("can't sync file", _e))?;
+        self.stats.lock().sync_time += start.elapsed();
+        Ok(())
+    }
+
+    //文件增长时预先分配空间并同步文件大小，避免之后每次 fdatasync 都要更新元数据
+    pub(crate) fn grow(&self, size: u64) -> NKResult<()> {
+        if self.options.no_grow_sync || self.options.read_only {
+            return Ok(());
+        }
+        let file = self.file.try_read().unwrap();
+        let file_size = file.metadata().map_err(|_e| ("can't stat file", _e))?.len();
+        if size <= file_size {
+            return Ok(());
+        }
+        file.set_len(size)
+            .map_err(|_e| ("can't truncate file", _e))?;
+        file.sync_all().map_err(|_e| ("can't sync file", _e))?;
         Ok(())
     }
 
@@ -360,10 +847,10 @@ impl DBImpl {
             if let Some(p) = self.page_pool.try_write().unwrap().pop() {
                 OwnerPage::from_vec(p)
             } else {
-                OwnerPage::from_vec(vec![0u8; get_page_size()])
+                OwnerPage::from_vec(vec![0u8; self.get_page_size()])
             }
         } else {
-            OwnerPage::from_vec(vec![0u8; get_page_size() * count])
+            OwnerPage::from_vec(vec![0u8; self.get_page_size() * count])
         };
 
         let p = page.to_page_mut();
@@ -374,19 +861,21 @@ impl DBImpl {
         if p.id != 0 {
             return Ok(page);
         }
-        p.id = (*(self.rwtx.try_write().unwrap().as_ref().unwrap().0))
+        p.id = (*(self.rwtx.try_write().unwrap().as_ref().unwrap()))
             .meta
             .borrow()
             .pgid;
-        let minsz = (((p.id + count as Pgid + 1) as usize) * get_page_size()) as u64;
-        if minsz >= self.mmap.try_read().unwrap().db_size {
-            self.mmap
-                .try_write()
-                .unwrap()
-                .set_mmap(&self.file.try_read().unwrap(), minsz)?;
+        let minsz = (((p.id + count as Pgid + 1) as usize) * self.get_page_size()) as u64;
+        if minsz >= self.mmap.read_recursive().db_size {
+            //重新映射前要等所有读事务结束，读事务还在引用旧的映射
+            self.mmap.write().set_mmap(
+                &self.file.try_read().unwrap(),
+                minsz,
+                self.options.mmap_flags,
+            )?;
         }
 
-        (*(self.rwtx.try_write().unwrap().as_ref().unwrap().0))
+        (*(self.rwtx.try_write().unwrap().as_ref().unwrap()))
             .meta
             .borrow_mut()
             .pgid += count as Pgid;
@@ -394,25 +883,138 @@ impl DBImpl {
         Ok(page)
     }
 
-    pub(crate) fn page(&self, id: Pgid) -> *const Page {
-        self.mmap.try_read().unwrap().page(id)
+    pub(crate) fn page(&self, id: Pgid) -> NKResult<*const Page> {
+        let mmap = self.mmap.read_recursive();
+        let p = mmap.page(id);
+        if !self.options.page_checksum || mmap.is_checked(id) {
+            return Ok(p);
+        }
+        //overflow 也可能损坏，先确认整个页都在映射范围内
+        let end = (id + unsafe { (*p).overflow } as Pgid + 1) * mmap.page_size as Pgid;
+        if end > mmap.db_size || !unsafe { (*p).verify_checksum(mmap.page_size) } {
+            return Err(NKError::ErrPageChecksum { pgid: id });
+        }
+        mmap.set_checked(id);
+        Ok(p)
+    }
+
+    //写入的页自带校验和，之后不用再校验
+    pub(crate) fn set_checksum(&self, page: &mut OwnerPage) {
+        if self.options.page_checksum {
+            Page::set_checksum(&mut page.value);
+            self.mmap.read_recursive().set_checked(page.to_page().id);
+        }
+    }
+
+    //装下 size 字节需要的页数，开启校验和时要给页尾留出位置
+    pub(crate) fn page_count(&self, size: usize) -> usize {
+        let trailer = if self.options.page_checksum {
+            PageChecksumSize
+        } else {
+            0
+        };
+        (size + trailer).div_ceil(self.get_page_size())
     }
 
     pub(crate) fn get_page_size(&self) -> usize {
-        self.mmap.try_read().unwrap().page_size
+        self.mmap.read_recursive().page_size
+    }
+
+    pub(crate) fn read_only(&self) -> bool {
+        self.options.read_only
+    }
+
+    pub(crate) fn page_checksum(&self) -> bool {
+        self.options.page_checksum
     }
 
-    pub(crate) fn meta(&self) -> Meta {
-        self.mmap.try_read().unwrap().meta()
+    pub(crate) fn fill_percent(&self) -> f64 {
+        self.options.fill_percent
+    }
+
+    pub(crate) fn meta(&self) -> NKResult<Meta> {
+        Ok(self.snapshot()?.0)
+    }
+
+    //最近一次提交的 meta，WAL 模式下还有检查点之前提交的页
+    pub(crate) fn snapshot(&self) -> NKResult<(Meta, Option<WalPages>)> {
+        if let Some(wal) = &self.wal {
+            let wal = wal.lock();
+            if let Some(meta) = wal.meta {
+                return Ok((meta, Some(wal.pages.clone())));
+            }
+        }
+        Ok((self.mmap.read_recursive().meta()?, None))
+    }
+
+    //用有效的 meta 页重写损坏的那个，返回是否做了修复
+    fn repair_meta(&self) -> NKResult<bool> {
+        let (meta0, meta1) = {
+            let mmap = self.mmap.read_recursive();
+            unsafe { (*mmap.meta0, *mmap.meta1) }
+        };
+        let (good, bad_id) = match (meta0.validate(), meta1.validate()) {
+            (Ok(()), Ok(())) => return Ok(false),
+            (Ok(()), Err(_)) => (meta0, 1),
+            (Err(_), Ok(())) => (meta1, 0),
+            (Err(e), Err(_)) => return Err(e),
+        };
+        //事务 id 决定写到哪个 meta 页，内容和好的 meta 一样，事务 id 小 1
+        let mut meta = good;
+        meta.txid = good.txid.checked_sub(1).unwrap_or(1);
+        let page_size = self.get_page_size();
+        let mut buf = vec![0u8; page_size];
+        let p = self.page_in_buffer_mut(&mut buf, 0);
+        meta.write(p);
+        self.write_at(&buf, bad_id * page_size as u64)?;
+        self.sync()?;
+        Ok(true)
+    }
+
+    //把检查点之后提交的页和最后一次提交的 meta 写进数据文件并清空日志。数据页先同步，再写 meta。
+    //正在进行的读事务还在用自己拿到的那份页
+    pub(crate) fn checkpoint(&self) -> NKResult<()> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        unsafe {
+            self.rw_lock.raw().lock();
+        }
+        let res = (|| -> NKResult<()> {
+            let (meta, pages) = {
+                let wal = wal.lock();
+                (wal.meta, wal.pages.clone())
+            };
+            if let Some(mut meta) = meta {
+                let page_size = self.get_page_size();
+                let mut ids: Vec<Pgid> = pages.keys().copied().collect();
+                ids.sort_unstable();
+                for id in ids {
+                    self.write_at(&pages[&id].value, id * page_size as u64)?;
+                }
+                self.sync()?;
+                let mut buf = vec![0u8; page_size];
+                let p = self.page_in_buffer_mut(&mut buf, 0);
+                meta.write(p);
+                let id = p.id;
+                self.write_at(&buf, id * page_size as u64)?;
+                self.sync()?;
+            }
+            wal.lock().reset()
+        })();
+        unsafe {
+            self.rw_lock.raw().unlock();
+        }
+        res
     }
 
     pub(crate) fn page_in_buffer_mut<'a>(&self, buf: &'a mut [u8], id: u32) -> &'a mut Page {
-        self.mmap.try_write().unwrap().page_in_buffer_mut(buf, id)
+        self.mmap.read_recursive().page_in_buffer_mut(buf, id)
     }
 
-    pub(crate) fn remove_tx(&self, tx: Tx) {
+    pub(crate) fn remove_tx(&self, tx: &Arc<TxImpl>) {
         let mut txs = self.txs.try_write().unwrap();
-        let index = txs.iter().position(|t| Arc::ptr_eq(&tx.0, &t.0)).unwrap();
+        let index = txs.iter().position(|t| Arc::ptr_eq(tx, t)).unwrap();
         txs.remove(index);
     }
 }
@@ -463,8 +1065,8 @@ mod tests {
 
         db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
             let b = tx.bucket("default".as_bytes())?;
-            let v = b.get(b"abc").unwrap();
-            println!("value:{:?}", str::from_utf8(v).unwrap());
+            let v = b.get(b"abc")?.unwrap();
+            println!("value:{:?}", str::from_utf8(&v).unwrap());
             Ok(())
         }))
         .unwrap();
@@ -473,11 +1075,11 @@ mod tests {
     #[test]
     fn test_tx_delete() {
         let mut db = DBImpl::open("./test.db", DEFAULT_OPTIONS).unwrap();
-        let mut tx1 = db.begin_rwtx();
+        let mut tx1 = db.begin_rwtx().unwrap();
         tx1.create_bucket("888".as_bytes()).unwrap();
         tx1.commit();
         db.print();
-        let mut tx2 = db.begin_rwtx();
+        let mut tx2 = db.begin_rwtx().unwrap();
         let b = tx2.bucket("888".as_bytes()).unwrap();
         b.put(b"001", b"aaa");
         b.put(b"002", b"bbb");
@@ -486,7 +1088,7 @@ mod tests {
         tx2.commit();
         db.print();
 
-        let mut tx3 = db.begin_rwtx();
+        let mut tx3 = db.begin_rwtx().unwrap();
         let b = tx3.bucket("888".as_bytes()).unwrap();
         b.delete(b"001");
         tx3.commit();
@@ -498,4 +1100,496 @@ mod tests {
         let mut db = DBImpl::open("./test.db", DEFAULT_OPTIONS).unwrap();
         db.print();
     }
+
+    #[test]
+    fn test_db_grow_sync() {
+        let path = crate::test_db_path("grow_sync");
+        let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let b = tx.create_bucket(b"default")?;
+            for i in 0..1000 {
+                b.put(format!("{:04}", i).as_bytes(), &[1u8; 100])?;
+            }
+            Ok(())
+        }))
+        .unwrap();
+        let meta = db.0.meta().unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(size, (meta.pgid + 1) * meta.page_size as u64);
+        drop(db);
+
+        let options = DEFAULT_OPTIONS.no_sync(true).no_grow_sync(true);
+        let db = DB::open(&path, options).unwrap();
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            tx.bucket(b"default")?.put(b"nosync", b"1")?;
+            Ok(())
+        }))
+        .unwrap();
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            assert_eq!(
+                tx.bucket(b"default")?.get(b"nosync")?.as_deref(),
+                Some(&b"1"[..])
+            );
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_db_file_lock() {
+        let path = crate::test_db_path("file_lock");
+        let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+        db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            tx.create_bucket(b"default")?;
+            Ok(())
+        }))
+        .unwrap();
+        let options = DEFAULT_OPTIONS.timeout(Duration::from_millis(100));
+        assert!(matches!(DB::open(&path, options), Err(NKError::ErrTimeout)));
+        drop(db);
+        let db = DB::open(&path, options).unwrap();
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            tx.bucket(b"default")?;
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_db_read_only() {
+        let path = crate::test_db_path("read_only");
+        let read_only = DEFAULT_OPTIONS.read_only(true);
+        assert!(DB::open(&path, read_only).is_err());
+        {
+            let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+            db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                tx.create_bucket(b"default")?.put(b"abc", b"123")?;
+                Ok(())
+            }))
+            .unwrap();
+            assert!(matches!(
+                db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                    tx.bucket(b"default")?.put(b"abc", b"456")
+                })),
+                Err(NKError::ErrTxNotWritable)
+            ));
+        }
+        //多个只读实例可以同时打开同一个文件
+        let db1 = DB::open(&path, read_only).unwrap();
+        let db2 = DB::open(&path, read_only).unwrap();
+        for db in [&db1, &db2] {
+            db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                assert_eq!(
+                    tx.bucket(b"default")?.get(b"abc")?.as_deref(),
+                    Some(&b"123"[..])
+                );
+                assert!(matches!(
+                    tx.bucket(b"default")?.put(b"abc", b"456"),
+                    Err(NKError::ErrDatabaseReadOnly)
+                ));
+                assert!(matches!(
+                    tx.create_bucket(b"other"),
+                    Err(NKError::ErrDatabaseReadOnly)
+                ));
+                Ok(())
+            }))
+            .unwrap();
+        }
+        assert!(matches!(
+            db1.update(Box::new(|_tx: &mut Tx| -> NKResult<()> { Ok(()) })),
+            Err(NKError::ErrDatabaseReadOnly)
+        ));
+        let options = DEFAULT_OPTIONS.timeout(Duration::from_millis(100));
+        assert!(matches!(DB::open(&path, options), Err(NKError::ErrTimeout)));
+    }
+
+    #[test]
+    fn test_db_repair_meta() {
+        let path = crate::test_db_path("repair_meta");
+        let options = DEFAULT_OPTIONS.page_size(4096);
+        {
+            let db = DB::open(&path, options).unwrap();
+            db.update(|tx| -> NKResult<()> {
+                tx.create_bucket(b"first")?;
+                Ok(())
+            })
+            .unwrap();
+            db.update(|tx| -> NKResult<()> {
+                tx.create_bucket(b"second")?;
+                Ok(())
+            })
+            .unwrap();
+        }
+        //事务 3 的 meta 写在第 1 页，写坏之后退回到事务 2
+        let corrupt = |id: u64| {
+            let f = OpenOptions::new().write(true).open(&path).unwrap();
+            f.write_at(&[0xFF; 8], id * 4096 + Page::header_size() as u64 + 24)
+                .unwrap();
+        };
+        corrupt(1);
+        {
+            let db = DB::open(&path, options).unwrap();
+            db.view(|tx| -> NKResult<()> {
+                tx.bucket(b"first")?;
+                assert!(tx.bucket(b"second").is_err());
+                Ok(())
+            })
+            .unwrap();
+            assert!(db.repair_meta().unwrap());
+            assert!(!db.repair_meta().unwrap());
+        }
+        //修复之后另一个 meta 页坏了也能打开
+        corrupt(0);
+        let db = DB::open(&path, options).unwrap();
+        db.view(|tx| -> NKResult<()> {
+            tx.bucket(b"first")?;
+            Ok(())
+        })
+        .unwrap();
+        drop(db);
+        corrupt(1);
+        assert!(matches!(
+            DB::open(&path, options),
+            Err(NKError::ErrChecksum)
+        ));
+    }
+
+    #[test]
+    fn test_db_page_checksum() {
+        let path = crate::test_db_path("page_checksum");
+        let options = DEFAULT_OPTIONS.page_size(4096).page_checksum(true);
+        let root = {
+            let db = DB::open(&path, options).unwrap();
+            db.update(|tx| -> NKResult<()> {
+                let b = tx.create_bucket(b"default")?;
+                for i in 0..100u32 {
+                    b.put(format!("key{:03}", i).as_bytes(), &[1u8; 20])?;
+                }
+                //超过一页的值，校验和在溢出页的末尾
+                b.put(b"large", &[2u8; 10000])?;
+                Ok(())
+            })
+            .unwrap();
+            let mut tx = db.begin(false).unwrap();
+            let root = tx.bucket(b"default").unwrap().ibucket.root;
+            assert!(tx.check().is_empty());
+            root
+        };
+        {
+            let db = DB::open(&path, options).unwrap();
+            db.view(|tx| -> NKResult<()> {
+                assert_eq!(
+                    tx.bucket_ref(b"default")?.get(b"large")?.as_deref(),
+                    Some(&[2u8; 10000][..])
+                );
+                Ok(())
+            })
+            .unwrap();
+            //重新映射后要重新校验
+            assert!(db.0.mmap.read().is_checked(root));
+            let size = db.0.mmap.read().db_size;
+            db.0.mmap
+                .write()
+                .set_mmap(&db.0.file.read(), size * 2, 0)
+                .unwrap();
+            assert!(!db.0.mmap.read().is_checked(root));
+        }
+
+        //改坏 bucket 根页里的一个字节
+        let f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.write_at(&[0xFF], root * 4096 + Page::header_size() as u64 + 3)
+            .unwrap();
+        let db = DB::open(&path, options).unwrap();
+        let err = db.view(|tx| -> NKResult<()> {
+            tx.bucket_ref(b"default")?.get(b"key000")?;
+            Ok(())
+        });
+        assert!(matches!(err, Err(NKError::ErrPageChecksum { pgid }) if pgid == root));
+        db.view(|tx| -> NKResult<()> {
+            let mut r = tx.bucket_ref(b"default")?.range::<[u8], _>(..);
+            assert!(r.next().is_none());
+            assert!(matches!(r.error(), Some(NKError::ErrPageChecksum { pgid }) if *pgid == root));
+            let errs = tx.check();
+            assert!(matches!(errs[0], NKError::ErrPageChecksum { pgid } if pgid == root));
+            Ok(())
+        })
+        .unwrap();
+        drop(db);
+        //不开校验和时不检查
+        DB::open(&path, options.page_checksum(false))
+            .unwrap()
+            .view(|tx| -> NKResult<()> {
+                tx.bucket(b"default")?;
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_db_features() {
+        use crate::comparator::REVERSE;
+        use crate::page::FeatureComparator;
+
+        let path = crate::test_db_path("features");
+        let options = DEFAULT_OPTIONS.page_size(4096);
+        {
+            let db = DB::open(&path, options).unwrap();
+            assert_eq!(db.0.meta().unwrap().flags, 0);
+            db.update(|tx| -> NKResult<()> {
+                tx.create_bucket_with_comparator(b"reverse", REVERSE)?;
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(db.0.meta().unwrap().flags, FeatureComparator);
+        }
+        {
+            let db = DB::open(&path, options.page_checksum(true)).unwrap();
+            db.update(|tx| -> NKResult<()> {
+                tx.create_bucket(b"default")?;
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(
+                db.0.meta().unwrap().flags,
+                FeatureComparator | FeaturePageChecksum
+            );
+        }
+        //不认识的特性拒绝打开
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        for id in 0..2u64 {
+            let mut buf = vec![0u8; 4096];
+            f.read_exact_at(&mut buf, id * 4096).unwrap();
+            let m = Page::from_buf_mut(&mut buf).meta_mut();
+            m.flags |= 0x100;
+            m.checksum = m.sum64();
+            f.write_at(&buf, id * 4096).unwrap();
+        }
+        assert!(matches!(
+            DB::open(&path, options.read_only(true)),
+            Err(NKError::ErrUnsupportedFeatures(0x100))
+        ));
+    }
+
+    #[test]
+    fn test_mmap_size() {
+        let m = MmapUtil {
+            page_size: 4096,
+            ..Default::default()
+        };
+        assert_eq!(m.mmap_size(0).unwrap(), 1 << 15);
+        assert_eq!(m.mmap_size((1 << 20) + 1).unwrap(), 1 << 21);
+        //超过 1GB 以后按 1GB 增长
+        assert_eq!(m.mmap_size((1 << 30) + 1).unwrap(), 2 << 30);
+        assert_eq!(m.mmap_size(5 << 30).unwrap(), 5 << 30);
+        assert!(m.mmap_size(MAX_MAP_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn test_db_options() {
+        let path = crate::test_db_path("options");
+        for options in [
+            DEFAULT_OPTIONS.page_size(1000),
+            DEFAULT_OPTIONS.page_size(512),
+            DEFAULT_OPTIONS.fill_percent(0.0),
+            DEFAULT_OPTIONS.mmap_flags(0x1),
+            DEFAULT_OPTIONS.initial_mmap_size(MAX_MAP_SIZE + 1),
+        ] {
+            assert!(matches!(
+                DB::open(&path, options),
+                Err(NKError::ErrInvalidOption(_))
+            ));
+        }
+        let options = DEFAULT_OPTIONS
+            .page_size(1024)
+            .initial_mmap_size(1 << 20)
+            .mmap_flags(MAP_POPULATE)
+            .fill_percent(1.0);
+        {
+            let db = DB::open(&path, options).unwrap();
+            assert_eq!(db.0.get_page_size(), 1024);
+            db.update(Box::new(|tx: &mut Tx| -> NKResult<()> {
+                let b = tx.create_bucket(b"default")?;
+                assert_eq!(b.fill_percent, 1.0);
+                for i in 0..1000u32 {
+                    b.put(format!("key{:04}", i).as_bytes(), b"value")?;
+                }
+                Ok(())
+            }))
+            .unwrap();
+        }
+        //已存在的文件沿用创建时的页大小
+        let db = DB::open(&path, DEFAULT_OPTIONS.page_size(4096)).unwrap();
+        assert_eq!(db.0.get_page_size(), 1024);
+        db.view(Box::new(|tx: &mut Tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            assert_eq!(b.fill_percent, DEFAULT_FILL_PERCENT);
+            for i in 0..1000u32 {
+                assert_eq!(
+                    b.get(format!("key{:04}", i).as_bytes())?.as_deref(),
+                    Some(&b"value"[..])
+                );
+            }
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_db_begin() {
+        let db = DB::open(&crate::test_db_path("begin"), DEFAULT_OPTIONS).unwrap();
+        let mut tx = db.begin(true).unwrap();
+        tx.create_bucket(b"default")
+            .unwrap()
+            .put(b"abc", b"123")
+            .unwrap();
+        tx.commit().unwrap();
+        assert!(matches!(tx.commit(), Err(NKError::ErrTxClosed)));
+        assert!(matches!(tx.rollback(), Err(NKError::ErrTxClosed)));
+        drop(tx);
+
+        //没有提交的写事务在 drop 时回滚并释放写锁
+        {
+            let mut tx = db.begin(true).unwrap();
+            tx.bucket(b"default").unwrap().put(b"abc", b"456").unwrap();
+        }
+
+        let mut reader = db.begin(false).unwrap();
+        assert!(matches!(reader.commit(), Err(NKError::ErrTxNotWritable)));
+        assert_eq!(
+            reader
+                .bucket(b"default")
+                .unwrap()
+                .get(b"abc")
+                .unwrap()
+                .as_deref(),
+            Some(&b"123"[..])
+        );
+        let db2 = db.clone();
+        let writer = thread::spawn(move || {
+            let mut tx = db2.begin(true).unwrap();
+            tx.bucket(b"default").unwrap().put(b"abc", b"789").unwrap();
+            for i in 0..5000u32 {
+                tx.bucket(b"default")
+                    .unwrap()
+                    .put(format!("key{:05}", i).as_bytes(), &[0u8; 100])
+                    .unwrap();
+            }
+            //文件变大需要重新映射，会等读事务结束
+            tx.commit().unwrap();
+        });
+        thread::sleep(Duration::from_millis(100));
+        //读事务看到的是开始时的快照
+        assert_eq!(
+            reader
+                .bucket(b"default")
+                .unwrap()
+                .get(b"abc")
+                .unwrap()
+                .as_deref(),
+            Some(&b"123"[..])
+        );
+        drop(reader);
+        writer.join().unwrap();
+        assert!(db.0.txs.read().is_empty());
+
+        let mut tx = db.begin(false).unwrap();
+        assert_eq!(
+            tx.bucket(b"default")
+                .unwrap()
+                .get(b"abc")
+                .unwrap()
+                .as_deref(),
+            Some(&b"789"[..])
+        );
+    }
+
+    #[test]
+    fn test_db_update_view_result() {
+        #[derive(Debug)]
+        enum AppError {
+            DB(NKError),
+            NotFound,
+        }
+
+        impl From<NKError> for AppError {
+            fn from(e: NKError) -> Self {
+                AppError::DB(e)
+            }
+        }
+
+        let db = DB::open(&crate::test_db_path("update_view_result"), DEFAULT_OPTIONS).unwrap();
+        let count = db
+            .update(|tx| -> NKResult<usize> {
+                let b = tx.create_bucket(b"default")?;
+                b.put(b"abc", b"123")?;
+                b.put(b"def", b"456")?;
+                Ok(2)
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+
+        let v = db
+            .view(|tx| -> NKResult<Vec<u8>> {
+                Ok(tx.bucket(b"default")?.get(b"abc")?.unwrap().to_vec())
+            })
+            .unwrap();
+        assert_eq!(v, b"123");
+
+        let r = db.view(|tx| -> Result<Vec<u8>, AppError> {
+            let b = tx.bucket(b"default")?;
+            b.get(b"xyz")?.map(|v| v.to_vec()).ok_or(AppError::NotFound)
+        });
+        assert!(matches!(r, Err(AppError::NotFound)));
+        let r = db.view(|tx| -> Result<(), AppError> {
+            tx.bucket(b"missing")?;
+            Ok(())
+        });
+        assert!(matches!(r, Err(AppError::DB(NKError::ErrBucketNotFound))));
+
+        //返回错误时事务回滚
+        let r = db.update(|tx| -> Result<(), AppError> {
+            tx.bucket(b"default")?.put(b"abc", b"789")?;
+            Err(AppError::NotFound)
+        });
+        assert!(r.is_err());
+        db.view(|tx| -> NKResult<()> {
+            assert_eq!(
+                tx.bucket(b"default")?.get(b"abc")?.as_deref(),
+                Some(&b"123"[..])
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_db_stats() {
+        let db = DB::open(&crate::test_db_path("stats"), DEFAULT_OPTIONS).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            let b = tx.create_bucket(b"default")?;
+            for i in 0..1000u32 {
+                b.put(format!("key{:04}", i).as_bytes(), &[0u8; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let reader = db.begin(false).unwrap();
+        db.update(|tx| -> NKResult<()> { tx.bucket(b"default")?.delete(b"key0000") })
+            .unwrap();
+        let stats = db.stats();
+        assert_eq!(stats.commit_n, 2);
+        assert_eq!(stats.tx_n, 1);
+        assert_eq!(stats.open_tx_n, 1);
+        //读事务还在，旧页面只能挂在 pending 里
+        assert!(stats.pending_page_n > 0);
+        assert!(stats.freelist_inuse > 0);
+        assert!(stats.write_time > Duration::ZERO);
+        assert!(stats.sync_time > Duration::ZERO);
+        drop(reader);
+        assert_eq!(db.stats().open_tx_n, 0);
+    }
 }
@@ -12,12 +12,22 @@ pub enum NKError {
     Unexpected(String),
     #[error("db open fail: {0}")]
     DBOpenFail(io::Error),
+    #[error("timeout waiting for the database file lock")]
+    ErrTimeout,
+    #[error("invalid option: {0}")]
+    ErrInvalidOption(String),
     #[error("invalid database")]
     ErrInvalid,
     #[error("version mismatch")]
     ErrVersionMismatch,
     #[error("checksum error")]
     ErrChecksum,
+    #[error("unsupported format features: {0:#x}")]
+    ErrUnsupportedFeatures(u32),
+    #[error("database was created by an older version, open it read-only or upgrade it")]
+    ErrUpgradeRequired,
+    #[error("write-ahead log has committed transactions, open the database writable to replay it")]
+    ErrWalReplayRequired,
     #[error("incompatible value")]
     ErrIncompatibleValue,
     #[error("{0} bucket exists")]
@@ -32,6 +42,36 @@ pub enum NKError {
     ErrBucketNotFound,
     #[error("IncompatibleValue")]
     IncompatibleValue,
+    #[error("bucket was created with comparator {0}")]
+    ErrComparatorMismatch(u16),
+    #[error("comparator id {0} is reserved")]
+    ErrComparatorReserved(u16),
+    #[error("comparator {0} is not built in and can't be used in WAL mode")]
+    ErrWalComparator(u16),
+    #[error("compression {0} is not enabled in this build")]
+    ErrCompressionUnsupported(u8),
+    #[error("compression error: {0}")]
+    ErrCompression(String),
+    #[error("database is in read-only mode")]
+    ErrDatabaseReadOnly,
+    #[error("tx not writable")]
+    ErrTxNotWritable,
+    #[error("tx closed")]
+    ErrTxClosed,
+    #[error("page {0}: out of bounds: {1}")]
+    ErrPageOutOfBounds(u64, u64),
+    #[error("page {0}: multiple references")]
+    ErrPageMultipleReferences(u64),
+    #[error("page {0}: reachable freed")]
+    ErrPageFreed(u64),
+    #[error("page {0}: unreachable unfreed")]
+    ErrPageLeaked(u64),
+    #[error("page {0}: invalid type: {1:#x}")]
+    ErrPageInvalidType(u64, u16),
+    #[error("page {0}: unsorted keys at index {1}")]
+    ErrPageUnsortedKeys(u64, usize),
+    #[error("page {pgid}: checksum mismatch")]
+    ErrPageChecksum { pgid: u64 },
 }
 
 impl From<&str> for NKError {
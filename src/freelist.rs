@@ -1,6 +1,6 @@
 use crate::{
     error::NKResult,
-    page::{FreeListPageFlag, Page, Pgid},
+    page::{FreeListPageFlag, Page, Pgid, WideCountPageFlag},
     tx::Txid,
 };
 use std::collections::HashMap;
@@ -24,11 +24,8 @@ impl Default for FreeList {
 
 impl FreeList {
     pub(crate) fn size(&self) -> usize {
-        let mut count = self.count();
-        if count > 0xFFFF {
-            count += 1;
-        }
-        return Page::header_size() + size_of::<Pgid>() * count;
+        let count = self.count();
+        Page::header_size_for(count) + size_of::<Pgid>() * count
     }
 
     fn count(&self) -> usize {
@@ -107,20 +104,21 @@ impl FreeList {
     }
 
     pub(crate) fn read(&mut self, p: &Page) {
-        let mut idx: usize = 0;
-        let mut count = p.count as usize;
-        if count == 0xFFFF {
-            idx = 1;
-            count = *p.freelist().first().unwrap() as usize;
+        self.ids = Self::page_ids(p).to_vec();
+        self.ids.sort_unstable();
+        self.reindex();
+    }
+
+    // 版本 2 的文件超过 0xFFFF 个时不设 WideCountPageFlag，第一个元素存放实际的个数
+    pub(crate) fn page_ids(p: &Page) -> &[Pgid] {
+        if p.count != 0xFFFF || p.flags & WideCountPageFlag != 0 {
+            return p.freelist();
         }
-        if count == 0 {
-            self.ids.clear();
-        } else {
-            let ids = p.freelist();
-            self.ids = ids[idx..count].to_vec();
-            self.ids.sort_unstable();
+        let ptr = p.data_ptr() as *const Pgid;
+        unsafe {
+            let count = *ptr as usize;
+            std::slice::from_raw_parts(ptr.add(1), count)
         }
-        self.reindex();
     }
 
     pub(crate) fn reindex(&mut self) {
@@ -138,22 +136,10 @@ impl FreeList {
 
     pub(crate) fn write(&self, p: &mut Page) {
         p.flags |= FreeListPageFlag;
-
-        let count = self.count();
-        if count == 0 {
-            p.count = count as u16;
-        } else if count < 0xFFFF {
-            p.count = count as u16;
-            let m = p.freelist_mut();
-            self.copy_all(m);
-            m.sort_unstable();
-        } else {
-            p.count = 0xFFFF;
-            let m = p.freelist_mut();
-            m[0] = count as u64;
-            self.copy_all(&mut m[1..]);
-            m[1..].sort_unstable();
-        }
+        p.set_count(self.count());
+        let m = p.freelist_mut();
+        self.copy_all(m);
+        m.sort_unstable();
     }
 
     pub(crate) fn copy_all(&self, mut dst: &mut [Pgid]) {
@@ -265,4 +251,35 @@ mod tests {
         freelist.copy_all(&mut dst);
         dst.sort_unstable();
     }
+
+    #[test]
+    fn test_write_wide_count() {
+        for count in [0usize, 3, 0xFFFE, 0xFFFF, 0x10003] {
+            let ids: Vec<Pgid> = (2..count as Pgid + 2).rev().collect();
+            let freelist = FreeList {
+                ids: ids.clone(),
+                pending: HashMap::new(),
+                cache: HashMap::new(),
+            };
+            let mut buf = vec![0u8; freelist.size()];
+            let p = Page::from_buf_mut(&mut buf);
+            freelist.write(p);
+            assert_eq!(p.flags & WideCountPageFlag != 0, count >= 0xFFFF);
+            assert_eq!(p.count(), count);
+            let mut sorted = ids;
+            sorted.sort_unstable();
+            assert_eq!(FreeList::page_ids(p), &sorted[..]);
+        }
+
+        //版本 2 写的 freelist 页没有 WideCountPageFlag
+        let mut buf = vec![0u8; Page::header_size() + size_of::<Pgid>() * 4];
+        let p = Page::from_buf_mut(&mut buf);
+        p.flags = FreeListPageFlag;
+        p.count = 0xFFFF;
+        unsafe {
+            let m = std::slice::from_raw_parts_mut(p.data_ptr_mut() as *mut Pgid, 4);
+            m.copy_from_slice(&[3, 2, 4, 5]);
+        }
+        assert_eq!(FreeList::page_ids(p), &[2, 4, 5]);
+    }
 }
@@ -1,14 +1,25 @@
-mod bucket;
-mod cursor;
+mod batch;
+pub mod bucket;
+mod compact;
+pub mod comparator;
+pub mod compression;
+pub mod cursor;
 pub mod db;
 pub mod error;
 mod freelist;
 mod node;
 mod page;
 pub mod tx;
+mod upgrade;
+mod wal;
+
+pub use compact::{compact, compact_with_comparators};
+pub use upgrade::upgrade;
 
 pub(crate) const magic: u32 = 0xED0CDAED;
-pub(crate) const version: u32 = 2;
+pub(crate) const version: u32 = 3;
+//更早的版本只能只读打开，用 upgrade 升级
+pub(crate) const min_version: u32 = 2;
 
 pub(crate) fn u8_to_struct_mut<T>(buf: &mut [u8]) -> &mut T {
     let s = unsafe { &mut *(buf.as_mut_ptr() as *mut u8 as *mut T) };
@@ -19,3 +30,10 @@ pub(crate) fn u8_to_struct<T>(buf: &[u8]) -> &T {
     let s = unsafe { &*(buf.as_ptr() as *const u8 as *const T) };
     s
 }
+
+#[cfg(test)]
+pub(crate) fn test_db_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("nikidb_{}_{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+}
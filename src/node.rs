@@ -1,9 +1,10 @@
 use crate::bucket::{Bucket, IBucket, MAX_FILL_PERCENT, MIN_FILL_PERCENT};
+use crate::comparator::{Comparator, BYTEWISE};
 use crate::db::DBImpl;
 use crate::error::NKResult;
 use crate::page::{
     BranchPageElementSize, BranchPageFlag, BucketLeafFlag, LeafPageElementSize, LeafPageFlag, Page,
-    Pgid, MIN_KEY_PERPAGE,
+    Pgid, MAX_KEY_PERPAGE, MIN_KEY_PERPAGE,
 };
 use crate::tx::TxImpl;
 use std::cell::{Ref, RefCell, RefMut};
@@ -23,6 +24,7 @@ pub(crate) struct NodeImpl {
     pub(crate) parent: Option<Weak<RefCell<NodeImpl>>>,
     unbalanced: bool,
     spilled: bool,
+    pub(crate) comparator: Comparator,
     pub(crate) pgid: Pgid,
     pub(crate) children: Vec<Node>,
     key: Option<Vec<u8>>,
@@ -36,6 +38,7 @@ impl NodeImpl {
             parent: None,
             unbalanced: false,
             spilled: false,
+            comparator: BYTEWISE,
             pgid: 0,
             children: Vec::new(),
             key: None,
@@ -52,6 +55,11 @@ impl NodeImpl {
         self
     }
 
+    pub fn comparator(mut self, comparator: Comparator) -> NodeImpl {
+        self.comparator = comparator;
+        self
+    }
+
     pub(crate) fn build(self) -> Node {
         Node(Rc::new(RefCell::new(self)))
     }
@@ -69,10 +77,10 @@ impl Node {
 
     pub(crate) fn child_at(
         &mut self,
-        bucket: &mut Bucket,
+        bucket: &Bucket,
         index: usize,
         parent: Option<Weak<RefCell<NodeImpl>>>,
-    ) -> Node {
+    ) -> NKResult<Node> {
         if self.node().is_leaf {
             panic!("invalid childAt{} on a leaf node", index);
         }
@@ -84,14 +92,14 @@ impl Node {
         if self.node().is_leaf {
             1
         } else {
-            2
+            MIN_KEY_PERPAGE
         }
     }
 
     pub(crate) fn size(&self) -> usize {
-        let mut sz = Page::header_size();
-        let elsz = self.page_element_size();
         let a = self.node();
+        let mut sz = Page::header_size_for(a.inodes.len());
+        let elsz = self.page_element_size();
         for i in 0..a.inodes.len() {
             let item = a.inodes.get(i).unwrap();
             sz += elsz + item.key.len() + item.value.len();
@@ -119,13 +127,13 @@ impl Node {
             println!("");
             if n.flags & BucketLeafFlag as u32 != 0 {
                 let ibucket = crate::u8_to_struct::<IBucket>(n.value.as_slice());
-                let p = unsafe { &*db.page(ibucket.root) };
+                let p = unsafe { &*db.page(ibucket.root).unwrap() };
                 let mut node = NodeImpl::new().build();
                 node.read(p);
                 node.print(db);
             }
             if n.pgid != 0 {
-                let p = unsafe { &*db.page(n.pgid) };
+                let p = unsafe { &*db.page(n.pgid).unwrap() };
                 let mut node = NodeImpl::new().build();
                 node.read(p);
                 node.print(db);
@@ -138,7 +146,7 @@ impl Node {
         let mut node_mut = self.node_mut();
         node_mut.pgid = p.id;
         node_mut.is_leaf = (p.flags & LeafPageFlag) != 0;
-        let count = p.count as usize;
+        let count = p.count();
         node_mut.inodes = Vec::with_capacity(count);
         for i in 0..count {
             let mut inode = INode::new();
@@ -165,11 +173,12 @@ impl Node {
     }
 
     pub(crate) fn del(&mut self, key: &[u8]) {
+        let compare = self.node().comparator.compare;
         let (exact, index) = {
             match self
                 .node()
                 .inodes
-                .binary_search_by(|inode| inode.key.as_slice().cmp(key))
+                .binary_search_by(|inode| compare(&inode.key, key))
             {
                 Ok(v) => (true, v),
                 Err(e) => (false, e),
@@ -195,11 +204,12 @@ impl Node {
         } else if new_key.len() <= 0 {
             panic!("put: zero-length new key")
         }
+        let compare = self.node().comparator.compare;
         let (exact, index) = {
             match self
                 .node()
                 .inodes
-                .binary_search_by(|inode| inode.key.as_slice().cmp(old_key))
+                .binary_search_by(|inode| compare(&inode.key, old_key))
             {
                 Ok(v) => (true, v),
                 Err(e) => (false, e),
@@ -225,20 +235,13 @@ impl Node {
         } else {
             p.flags = BranchPageFlag;
         }
-        if self.node().inodes.len() > 0xFFF {
-            panic!(
-                "inode overflow: {} (pgid={})",
-                self.node().inodes.len(),
-                p.id
-            );
-        }
-        p.count = self.node().inodes.len() as u16;
-        if p.count == 0 {
+        p.set_count(self.node().inodes.len());
+        if self.node().inodes.is_empty() {
             return;
         }
 
         let mut buf_ptr = unsafe {
-            p.data_ptr_mut()
+            p.elements_ptr_mut()
                 .add(self.page_element_size() * self.node().inodes.len())
         };
 
@@ -246,13 +249,13 @@ impl Node {
             assert!(item.key.len() > 0, "write: zero-length inode key");
             if self.node().is_leaf {
                 let elem = p.leaf_page_element_mut(i);
-                elem.pos = unsafe { buf_ptr.sub(elem.as_ptr() as usize) } as u32;
+                elem.pos = (buf_ptr as usize - elem.as_ptr() as usize) as u32;
                 elem.flags = item.flags as u32;
                 elem.ksize = item.key.len() as u32;
                 elem.vsize = item.value.len() as u32;
             } else {
                 let elem = p.branch_page_element_mut(i);
-                elem.pos = unsafe { buf_ptr.sub(elem.as_ptr() as usize) } as u32;
+                elem.pos = (buf_ptr as usize - elem.as_ptr() as usize) as u32;
                 elem.ksize = item.key.len() as u32;
                 elem.pgid = item.pgid;
                 assert!(elem.pgid != p.id, "write: circular dependency occurred");
@@ -277,16 +280,21 @@ impl Node {
     }
 
     fn child_index(&self, key: &[u8]) -> usize {
+        let compare = self.node().comparator.compare;
         match self
             .node()
             .inodes
-            .binary_search_by(|inode| inode.key.as_slice().cmp(key))
+            .binary_search_by(|inode| compare(&inode.key, key))
         {
             Ok(v) => v,
             Err(e) => e,
         }
     }
 
+    fn first_key(&self) -> Option<Vec<u8>> {
+        self.node().inodes.first().map(|inode| inode.key.clone())
+    }
+
     fn parent(&self) -> Option<Node> {
         match &self.node().parent {
             None => None,
@@ -294,28 +302,30 @@ impl Node {
         }
     }
 
-    fn next_sibling(&self, bucket: &mut Bucket) -> Option<Node> {
+    fn next_sibling(&self, bucket: &mut Bucket) -> NKResult<Option<Node>> {
         match self.parent() {
-            None => None,
+            None => Ok(None),
             Some(mut p) => {
                 let index = p.child_index(self.node().key.as_ref().unwrap());
-                if index as isize > self.num_children() as isize - 1 {
-                    return None;
+                if index + 1 >= p.num_children() {
+                    return Ok(None);
                 }
-                Some(p.child_at(bucket, index + 1, Some(Rc::downgrade(&p.0))))
+                p.child_at(bucket, index + 1, Some(Rc::downgrade(&p.0)))
+                    .map(Some)
             }
         }
     }
 
-    fn prev_sibling(&mut self, bucket: &mut Bucket) -> Option<Node> {
+    fn prev_sibling(&mut self, bucket: &mut Bucket) -> NKResult<Option<Node>> {
         match self.parent() {
-            None => None,
+            None => Ok(None),
             Some(mut p) => {
                 let index = p.child_index(self.node().key.as_ref().unwrap());
                 if index == 0 {
-                    return None;
+                    return Ok(None);
                 }
-                Some(p.child_at(bucket, index - 1, Some(Rc::downgrade(&p.0))))
+                p.child_at(bucket, index - 1, Some(Rc::downgrade(&p.0)))
+                    .map(Some)
             }
         }
     }
@@ -327,8 +337,8 @@ impl Node {
             db.freelist
                 .try_write()
                 .unwrap()
-                .free(bucket.tx().unwrap().meta.borrow().txid, unsafe {
-                    &*db.page(self.node().pgid)
+                .free(tx.meta.borrow().txid, unsafe {
+                    &*tx.raw_page(self.node().pgid)
                 });
             self.node_mut().pgid = 0;
         }
@@ -367,12 +377,18 @@ impl Node {
                 // 将root节点的叶子节点上移
                 // 创建一个新的子节点，以当前节点作为root节点
                 let pgid = self.node().inodes[0].pgid;
-                let mut child = bucket.node(pgid, Some(Rc::downgrade(&self.0)));
+                let mut child = bucket.node(pgid, Some(Rc::downgrade(&self.0)))?;
 
                 let mut node_mut = self.node_mut();
                 node_mut.is_leaf = child.node().is_leaf;
                 node_mut.inodes = child.node_mut().inodes.drain(..).collect();
                 node_mut.children = child.node_mut().children.drain(..).collect();
+                drop(node_mut);
+                for inode in self.node().inodes.iter() {
+                    if let Some(n) = bucket.nodes.borrow().get(&inode.pgid) {
+                        n.node_mut().parent = Some(Rc::downgrade(&self.0));
+                    }
+                }
                 //删除老得叶子节点
                 child.node_mut().parent = None;
                 bucket.nodes.borrow_mut().remove(&child.node().pgid);
@@ -399,10 +415,10 @@ impl Node {
         let use_next_sibing = p.child_index(self.node().key.as_ref().unwrap()) == 0; //找到需要rebalance的节点的位置
         let mut target = if use_next_sibing {
             //当前节点是最左边的节点
-            self.next_sibling(bucket).unwrap()
+            self.next_sibling(bucket)?.unwrap()
         } else {
             //左边的兄弟节点
-            self.prev_sibling(bucket).unwrap()
+            self.prev_sibling(bucket)?.unwrap()
         };
         // 如果当前节点和target节点都太小了，则合并他们
         if use_next_sibing {
@@ -472,14 +488,15 @@ impl Node {
 
     fn split_index(&self, threshold: usize) -> (usize, usize) {
         let mut index: usize = 0;
-        let mut sz: usize = 0;
+        let mut sz: usize = Page::header_size();
         let n = self.node();
-        let max = n.inodes.len() - MIN_KEY_PERPAGE;
-        let nodes = &n.inodes;
-        for (i, node) in nodes.iter().enumerate().take(max) {
+        let min_keys = self.min_keys();
+        let max = n.inodes.len() - min_keys;
+        for (i, node) in n.inodes.iter().enumerate() {
             index = i;
             let elsize = self.page_element_size() + node.key.len() + node.value.len();
-            if i > MIN_KEY_PERPAGE && sz + elsize > threshold {
+            //两边都至少保留 min_keys 个元素，叶子节点的大 value 可以单独占一组溢出页
+            if i >= min_keys && (i >= max || i >= MAX_KEY_PERPAGE || sz + elsize > threshold) {
                 break;
             }
             sz += elsize;
@@ -488,7 +505,8 @@ impl Node {
     }
 
     fn split_two(&mut self, page_size: usize, mut fill_percent: f64) -> Option<Node> {
-        if self.node().inodes.len() <= MIN_KEY_PERPAGE * 2 || self.node_less_than(page_size) {
+        let n = self.node().inodes.len();
+        if n < self.min_keys() * 2 || (n <= MAX_KEY_PERPAGE && self.node_less_than(page_size)) {
             return None;
         }
 
@@ -500,7 +518,10 @@ impl Node {
         let threshold = (page_size as f64 * fill_percent) as usize;
         let (split_index, _) = self.split_index(threshold);
 
-        let next = NodeImpl::new().leaf(self.node().is_leaf).build();
+        let next = NodeImpl::new()
+            .leaf(self.node().is_leaf)
+            .comparator(self.node().comparator)
+            .build();
         next.node_mut().inodes = self.node_mut().inodes.drain(split_index..).collect();
         Some(next)
     }
@@ -519,19 +540,26 @@ impl Node {
         return true;
     }
 
-    //node spill return parent
+    //node spill return root
     pub(crate) fn spill(&self, atx: Arc<TxImpl>, bucket: &Bucket) -> NKResult<Node> {
         if self.node().spilled {
             return Ok(self.clone());
         }
 
-        self.node_mut()
-            .children
-            .sort_by(|a, b| (*a).node().inodes[0].key.cmp(&(*b).node().inodes[0].key));
+        let compare = self.node().comparator.compare;
+        self.node_mut().children.sort_by(|a, b| {
+            compare(
+                &a.first_key().unwrap_or_default(),
+                &b.first_key().unwrap_or_default(),
+            )
+        });
 
-        let children = self.node().children.clone();
-        for child in children.iter() {
+        //子节点分裂时会往 children 里追加兄弟节点，所以每次都要重新检查长度
+        let mut i = 0;
+        while i < self.node().children.len() {
+            let child = self.node().children[i].clone();
             child.spill(atx.clone(), bucket)?;
+            i += 1;
         }
 
         self.node_mut().children.clear();
@@ -539,42 +567,42 @@ impl Node {
         let tx = atx.clone();
         let db = tx.db();
 
-        let mut nodes = self.split(db.get_page_size() as usize, bucket.fill_percent);
-
-        // 这里设置父节点信息
-        let parent_node = if nodes.len() == 1 {
-            if let Some(p) = &nodes.first().unwrap().node().parent {
-                p.upgrade().map(Node).clone()
-            } else {
-                None
-            }
-        } else {
-            if let Some(parent) = &self.node().parent {
-                let p = parent.upgrade().map(Node).unwrap();
-                for n in nodes.iter_mut() {
-                    n.node_mut().parent = Some(parent.clone());
+        let nodes = self.split(db.get_page_size() as usize, bucket.fill_percent);
+
+        // 这里设置父节点信息，根节点分裂时需要新建一个父节点
+        let mut new_root: Option<Node> = None;
+        if nodes.len() > 1 {
+            let parent = match self.parent() {
+                Some(p) => p,
+                None => {
+                    let p = NodeImpl::new()
+                        .leaf(false)
+                        .comparator(self.node().comparator)
+                        .build();
+                    p.node_mut().children.push(self.clone());
+                    self.node_mut().parent = Some(Rc::downgrade(&p.0));
+                    new_root = Some(p.clone());
+                    p
                 }
-                p.node_mut().children.extend_from_slice(&nodes[1..]);
-                Some(p)
-            } else {
-                let parent = NodeImpl::new().leaf(false).build();
-                parent
-                    .node_mut()
-                    .children
-                    .extend_from_slice(nodes.as_slice());
-                Some(parent)
+            };
+            for n in nodes[1..].iter() {
+                n.node_mut().parent = Some(Rc::downgrade(&parent.0));
+                parent.node_mut().children.push(n.clone());
             }
-        };
-        for n in nodes.iter_mut() {
+        }
+
+        for n in nodes.iter() {
             if n.node().pgid > 0 {
                 db.freelist
                     .try_write()
                     .unwrap()
-                    .free(tx.meta.borrow().txid, unsafe { &*db.page(n.node().pgid) });
+                    .free(tx.meta.borrow().txid, unsafe {
+                        &*tx.raw_page(n.node().pgid)
+                    });
                 n.node_mut().pgid = 0;
             }
 
-            let mut p = db.allocate(n.size() / db.get_page_size() as usize + 1)?;
+            let mut p = db.allocate(db.page_count(n.size()))?;
             let page = p.to_page_mut();
             if page.id >= tx.meta.borrow().pgid {
                 panic!(
@@ -588,28 +616,21 @@ impl Node {
             tx.pages.borrow_mut().insert(page.id, p);
             n.node_mut().spilled = true;
 
-            if let Some(parent) = &parent_node {
-                // let mut parent_node = parent.upgrade().map(Node).unwrap();
-                if let Some(key) = &n.node().key {
-                    let pgid = n.node().pgid;
-                    parent.put(key, key, &vec![], pgid, 0);
-                } else {
-                    let n1 = n.node();
-                    let inode = n1.inodes.first().unwrap();
+            if let Some(parent) = n.parent() {
+                if let Some(first) = n.first_key() {
+                    let key = n.node().key.clone().unwrap_or_else(|| first.clone());
                     let pgid = n.node().pgid;
-                    parent.put(&inode.key, &inode.key, &vec![], pgid, 0);
-                }
-                if n.node().parent.is_none() {
-                    n.node_mut().parent.replace(Rc::downgrade(&parent.0));
+                    parent.put(&key, &first, &[], pgid, 0);
+                    n.node_mut().key = Some(first);
                 }
             }
         }
 
-        if let Some(mut p) = parent_node {
-            p.node_mut().children.clear();
-            return p.spill(atx, bucket);
+        //根节点分裂出了新的根节点，继续 spill 新的根节点
+        if let Some(root) = new_root {
+            return root.spill(atx, bucket);
         }
-        return Ok(self.clone());
+        Ok(self.clone())
     }
 }
 
@@ -654,4 +675,30 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_node_split_max_keys() {
+        let node = NodeImpl::new().leaf(true).build();
+        let n = MAX_KEY_PERPAGE + 5000;
+        for i in 0..n {
+            let k = format!("{:06}", i);
+            node.put(k.as_bytes(), k.as_bytes(), b"", 0, 0);
+        }
+        //页足够大时只按元素个数分裂
+        let nodes = node.split(64 << 20, 1.0);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].node().inodes.len(), MAX_KEY_PERPAGE);
+
+        let mmap = MmapUtil::default();
+        let mut total = 0;
+        for n in nodes {
+            let mut buf = vec![0u8; n.size()];
+            let page = mmap.page_in_buffer_mut(&mut buf, 0);
+            n.write(page);
+            let mut read = NodeImpl::new().build();
+            read.read(page);
+            total += read.node().inodes.len();
+        }
+        assert_eq!(total, n);
+    }
 }
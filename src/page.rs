@@ -1,7 +1,7 @@
 use crate::bucket::IBucket;
 use crate::tx::Txid;
 use crate::{error::NKError, error::NKResult};
-use crate::{magic, version};
+use crate::{magic, min_version, version};
 use fnv::FnvHasher;
 use memoffset::offset_of;
 use std::hash::Hasher;
@@ -23,10 +23,29 @@ pub(crate) const BranchPageFlag: u16 = 0x01;
 pub(crate) const LeafPageFlag: u16 = 0x02;
 pub(crate) const MetaPageFlag: u16 = 0x04;
 pub(crate) const FreeListPageFlag: u16 = 0x10;
+//元素个数放不进 u16 的页：count 字段是 0xFFFF，页头之后的 8 个字节存放 u32 的个数，元素跟在后面
+pub(crate) const WideCountPageFlag: u16 = 0x20;
+//页（包括溢出页）的最后 8 个字节是校验和
+pub(crate) const ChecksumPageFlag: u16 = 0x8000;
+
+pub(crate) const PageChecksumSize: usize = size_of::<u64>();
+
+pub(crate) const WideCountSize: usize = size_of::<u64>();
 
 pub(crate) const BucketLeafFlag: u32 = 0x01;
+pub(crate) const CompressedLeafFlag: u32 = 0x02;
+
+//Meta.flags 记录文件用到的格式特性，有不认识的特性时拒绝打开
+pub(crate) const FeatureComparator: u32 = 0x01;
+pub(crate) const FeaturePageChecksum: u32 = 0x02;
+pub(crate) const FeatureCompression: u32 = 0x04;
+pub(crate) const FeatureWideCount: u32 = 0x08;
+pub(crate) const SupportedFeatures: u32 =
+    FeatureComparator | FeaturePageChecksum | FeatureCompression | FeatureWideCount;
 
 pub(crate) const MIN_KEY_PERPAGE: usize = 2;
+//分裂节点时每页最多放这么多元素，页头的 count 放得下，不需要 WideCountPageFlag
+pub(crate) const MAX_KEY_PERPAGE: usize = u16::MAX as usize - 1;
 #[repr(C)]
 //页数据
 pub(crate) struct Page {
@@ -138,10 +157,14 @@ impl Meta {
     pub(crate) fn validate(&self) -> NKResult<()> {
         if self.magic != magic {
             return Err(NKError::ErrInvalid);
-        } else if self.version != version {
+        } else if !(min_version..=version).contains(&self.version) {
             return Err(NKError::ErrVersionMismatch);
         } else if self.checksum != self.sum64() {
             return Err(NKError::ErrChecksum);
+        } else if self.flags & !SupportedFeatures != 0 {
+            return Err(NKError::ErrUnsupportedFeatures(
+                self.flags & !SupportedFeatures,
+            ));
         }
         Ok(())
     }
@@ -155,11 +178,46 @@ impl Meta {
     }
 }
 
+fn page_sum64(buf: &[u8]) -> u64 {
+    let mut h = FnvHasher::default();
+    h.write(buf);
+    h.finish()
+}
+
 impl Page {
     pub(crate) fn header_size() -> usize {
         offset_of!(Page, ptr)
     }
 
+    //放 count 个元素的页，元素之前的大小
+    pub(crate) fn header_size_for(count: usize) -> usize {
+        if count >= u16::MAX as usize {
+            Self::header_size() + WideCountSize
+        } else {
+            Self::header_size()
+        }
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        if self.flags & WideCountPageFlag != 0 {
+            unsafe { *(self.data_ptr() as *const u32) as usize }
+        } else {
+            self.count as usize
+        }
+    }
+
+    pub(crate) fn set_count(&mut self, count: usize) {
+        if count >= u16::MAX as usize {
+            self.flags |= WideCountPageFlag;
+            self.count = u16::MAX;
+            let n = u32::try_from(count).expect("page element count overflows u32");
+            unsafe { *(self.data_ptr_mut() as *mut u64) = n as u64 }
+        } else {
+            self.flags &= !WideCountPageFlag;
+            self.count = count as u16;
+        }
+    }
+
     pub(crate) fn from_buf_mut(buf: &mut [u8]) -> &mut Page {
         crate::u8_to_struct_mut::<Page>(buf)
     }
@@ -168,6 +226,29 @@ impl Page {
         crate::u8_to_struct::<Page>(buf)
     }
 
+    //校验和覆盖页头和数据，页头的 ChecksumPageFlag 也算在内
+    pub(crate) fn set_checksum(buf: &mut [u8]) {
+        Page::from_buf_mut(buf).flags |= ChecksumPageFlag;
+        let n = buf.len() - PageChecksumSize;
+        let sum = page_sum64(&buf[..n]);
+        buf[n..].copy_from_slice(&sum.to_le_bytes());
+    }
+
+    //没有校验和的页直接通过
+    pub(crate) fn verify_checksum(&self, page_size: usize) -> bool {
+        if self.flags & ChecksumPageFlag == 0 {
+            return true;
+        }
+        let buf = unsafe {
+            std::slice::from_raw_parts(
+                self as *const Page as *const u8,
+                (self.overflow as usize + 1) * page_size,
+            )
+        };
+        let n = buf.len() - PageChecksumSize;
+        buf[n..] == page_sum64(&buf[..n]).to_le_bytes()
+    }
+
     pub(crate) fn meta_mut(&mut self) -> &mut Meta {
         self.element_mut::<Meta>()
     }
@@ -177,13 +258,12 @@ impl Page {
     }
 
     fn elements<T>(&self) -> &[T] {
-        unsafe { std::slice::from_raw_parts(self.data_ptr() as *const T, self.count as usize) }
+        unsafe { std::slice::from_raw_parts(self.elements_ptr() as *const T, self.count()) }
     }
 
     fn elements_mut<T>(&mut self) -> &mut [T] {
-        unsafe {
-            std::slice::from_raw_parts_mut(self.data_ptr_mut() as *mut T, self.count as usize)
-        }
+        let count = self.count();
+        unsafe { std::slice::from_raw_parts_mut(self.elements_ptr_mut() as *mut T, count) }
     }
 
     fn element<T>(&self) -> &T {
@@ -241,6 +321,23 @@ impl Page {
     pub(crate) fn data_ptr(&self) -> *const u8 {
         &self.ptr as *const PhantomData<u8> as *const u8
     }
+
+    fn wide_count_size(&self) -> usize {
+        if self.flags & WideCountPageFlag != 0 {
+            WideCountSize
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn elements_ptr(&self) -> *const u8 {
+        unsafe { self.data_ptr().add(self.wide_count_size()) }
+    }
+
+    pub(crate) fn elements_ptr_mut(&mut self) -> *mut u8 {
+        let offset = self.wide_count_size();
+        unsafe { self.data_ptr_mut().add(offset) }
+    }
 }
 
 #[cfg(test)]
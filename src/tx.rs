@@ -1,25 +1,32 @@
-use crate::bucket::Bucket;
+use crate::bucket::{Bucket, BucketHeaderSize, IBucket};
+use crate::comparator::{Comparator, BYTEWISE};
 use crate::db::DBImpl;
 use crate::error::{NKError, NKResult};
-use crate::page::{Meta, OwnerPage, Page, Pgid};
+use crate::freelist::FreeList;
+use crate::page::{
+    BranchPageFlag, BucketLeafFlag, FeaturePageChecksum, FeatureWideCount, FreeListPageFlag,
+    LeafPageFlag, Meta, OwnerPage, Page, Pgid, WideCountPageFlag,
+};
+use crate::wal::{Op, WalPages};
 
 use lock_api::{RawMutex, RawRwLock};
 
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
 use std::sync::{Arc, RwLock, Weak};
+use std::time::Instant;
 pub(crate) type Txid = u64;
 
 pub struct Tx(pub(crate) Arc<TxImpl>);
 
-unsafe impl Sync for Tx {}
-unsafe impl Send for Tx {}
+unsafe impl Sync for TxImpl {}
+unsafe impl Send for TxImpl {}
 
 impl Tx {
-    pub(crate) fn clone(&self) -> Self {
-        Self(Arc::clone(&self.0))
-    }
-
     pub(crate) fn init(&mut self) {
         let r = self.0.clone();
         r.root.borrow_mut().weak_tx = Arc::downgrade(&self.0);
@@ -33,7 +40,31 @@ impl Tx {
             .root
             .borrow_mut()
             .create_bucket(name)
-            .map(|m| unsafe { &mut *m })
+            .map(|m| unsafe { &mut *(m as *mut Bucket) })
+    }
+
+    pub fn create_bucket_with_comparator(
+        &mut self,
+        name: &[u8],
+        comparator: Comparator,
+    ) -> NKResult<&mut Bucket> {
+        self.0
+            .root
+            .borrow_mut()
+            .create_bucket_with_comparator(name, comparator)
+            .map(|m| unsafe { &mut *(m as *mut Bucket) })
+    }
+
+    pub fn create_bucket_if_not_exists(&mut self, name: &[u8]) -> NKResult<&mut Bucket> {
+        self.0
+            .root
+            .borrow_mut()
+            .create_bucket_if_not_exists(name)
+            .map(|m| unsafe { &mut *(m as *mut Bucket) })
+    }
+
+    pub fn delete_bucket(&mut self, name: &[u8]) -> NKResult<()> {
+        self.0.root.borrow_mut().delete_bucket(name)
     }
 
     pub fn bucket(&mut self, name: &[u8]) -> NKResult<&mut Bucket> {
@@ -41,42 +72,191 @@ impl Tx {
             .root
             .borrow_mut()
             .bucket(name)
-            .map(|m| unsafe { &mut *m })
+            .map(|m| unsafe { &mut *(m as *mut Bucket) })
+    }
+
+    /// Retrieves a bucket for reading. Buckets and values obtained this way
+    /// borrow the transaction, so any number of them can be held at once.
+    pub fn bucket_ref(&self, name: &[u8]) -> NKResult<&Bucket> {
+        self.0
+            .root
+            .borrow()
+            .bucket_ref(name)
+            .map(|b| unsafe { &*(b as *const Bucket) })
     }
 
-    pub(crate) fn id(&self) -> Txid {
-        self.0.meta.borrow().txid
+    pub fn bucket_with_comparator(
+        &mut self,
+        name: &[u8],
+        comparator: Comparator,
+    ) -> NKResult<&mut Bucket> {
+        self.0
+            .root
+            .borrow_mut()
+            .bucket_with_comparator(name, comparator)
+            .map(|m| unsafe { &mut *(m as *mut Bucket) })
+    }
+
+    /// Like `bucket_ref`, for a bucket created with `comparator`.
+    pub fn bucket_ref_with_comparator(
+        &self,
+        name: &[u8],
+        comparator: Comparator,
+    ) -> NKResult<&Bucket> {
+        self.0
+            .root
+            .borrow()
+            .bucket_ref_with_comparator(name, comparator)
+            .map(|b| unsafe { &*(b as *const Bucket) })
     }
 
     fn tx(&self) -> Arc<TxImpl> {
         self.0.clone()
     }
 
+    /// Discards all changes of the transaction and releases its lock.
     pub fn rollback(&mut self) -> NKResult<()> {
+        if self.0.closed.get() {
+            return Err(NKError::ErrTxClosed);
+        }
         self._rollback()
     }
 
+    //重新加载 freelist 失败时也要关闭事务，否则写锁一直不会释放
     pub(crate) fn _rollback(&mut self) -> NKResult<()> {
         let db = self.0.db();
-        if self.0.writable {
+        let res = (|| -> NKResult<()> {
+            if !self.0.writable {
+                return Ok(());
+            }
             db.freelist
                 .try_write()
                 .unwrap()
-                .rollback(self.tx().meta.borrow().txid)?;
-            let free_page = db.page(db.meta().freelist);
+                .rollback(self.0.meta.borrow().txid)?;
+            let free_page = self.0.page(db.meta()?.freelist)?;
             db.freelist
                 .try_write()
                 .unwrap()
-                .reload(unsafe { &*free_page })?;
-        }
+                .reload(unsafe { &*free_page })
+        })();
         self.close();
-        Ok(())
+        res
     }
 
+    /// Writes a consistent snapshot of the database as seen by this transaction
+    /// to `w` and returns the number of bytes written. A writable transaction
+    /// writes the last committed state, without its own pending changes.
+    pub fn write_to<W: Write>(&self, mut w: W) -> NKResult<u64> {
+        let db = self.0.db();
+        let meta = self.snapshot_meta()?;
+        let page_size = db.get_page_size();
+
+        //重写两个 meta 页，另一个 meta 页的事务 id 小 1，拷贝出来的文件可以直接打开。
+        //事务 id 是 0 时另一个用 1，和修复 meta 页时一样
+        let mut buf = vec![0u8; page_size * 2];
+        for txid in [meta.txid, meta.txid.checked_sub(1).unwrap_or(1)] {
+            let mut m = meta;
+            m.txid = txid;
+            let p = db.page_in_buffer_mut(&mut buf, (txid % 2) as u32);
+            m.write(p);
+        }
+        w.write_all(&buf)
+            .map_err(|_e| ("can't write meta pages", _e))?;
+
+        //数据页直接从 mmap 拷贝，读事务结束前这些页不会被覆盖。WAL 模式下还没写进文件的页从内存拷贝
+        let size = (meta.pgid as usize - 2) * page_size;
+        let wal_page = |id| self.0.wal_pages.as_ref().and_then(|m| m.get(&id));
+        let mmap = db.mmap.read_recursive();
+        let mut id = 2;
+        while id < meta.pgid {
+            let data = match wal_page(id) {
+                Some(p) => &p.value[..],
+                None => {
+                    let mut end = id + 1;
+                    while end < meta.pgid && wal_page(end).is_none() {
+                        end += 1;
+                    }
+                    let len = (end - id) as usize * page_size;
+                    unsafe { std::slice::from_raw_parts(mmap.page(id) as *const u8, len) }
+                }
+            };
+            w.write_all(data)
+                .map_err(|_e| ("can't write data pages", _e))?;
+            id += (data.len() / page_size) as Pgid;
+        }
+        Ok((buf.len() + size) as u64)
+    }
+
+    /// Writes a snapshot of the database to a new file at `path`, see `write_to`.
+    pub fn copy_file<P: AsRef<Path>>(&self, path: P) -> NKResult<u64> {
+        let mut f = File::create(path).map_err(|_e| ("can't create file", _e))?;
+        let n = self.write_to(&mut f)?;
+        f.sync_all().map_err(|_e| ("can't sync file", _e))?;
+        Ok(n)
+    }
+
+    /// Checks the consistency of the database as seen by this transaction and
+    /// returns every problem found: pages referenced twice, reachable pages in
+    /// the freelist, leaked pages, unsorted keys and invalid page types. A
+    /// writable transaction checks the last committed state.
+    pub fn check(&self) -> Vec<NKError> {
+        let meta = match self.snapshot_meta() {
+            Ok(meta) => meta,
+            Err(e) => return vec![e],
+        };
+        let mut c = Checker {
+            tx: &self.0,
+            pgid: meta.pgid,
+            freed: HashSet::new(),
+            reachable: HashSet::new(),
+            errs: Vec::new(),
+        };
+        c.reachable.insert(0);
+        c.reachable.insert(1);
+        if c.mark(meta.freelist) {
+            let p = unsafe { &*self.0.raw_page(meta.freelist) };
+            if p.flags & FreeListPageFlag == 0 {
+                c.errs
+                    .push(NKError::ErrPageInvalidType(meta.freelist, p.flags));
+            } else {
+                c.freed.extend(FreeList::page_ids(p));
+            }
+        }
+        c.check_page(meta.root.root, Some(BYTEWISE));
+        for id in 0..meta.pgid {
+            if !c.reachable.contains(&id) && !c.freed.contains(&id) {
+                c.errs.push(NKError::ErrPageLeaked(id));
+            }
+        }
+        //可达的页又在 freelist 里
+        let mut freed: Vec<Pgid> = c.freed.intersection(&c.reachable).copied().collect();
+        freed.sort_unstable();
+        c.errs.extend(freed.into_iter().map(NKError::ErrPageFreed));
+        c.errs
+    }
+
+    //读事务使用开始时的 meta，写事务使用最近一次提交的 meta
+    fn snapshot_meta(&self) -> NKResult<Meta> {
+        if self.0.writable {
+            self.0.db().meta()
+        } else {
+            Ok(*self.0.meta.borrow())
+        }
+    }
+
+    /// Writes all changes to disk and releases the writer lock.
     pub fn commit(&mut self) -> NKResult<()> {
+        if self.0.closed.get() {
+            return Err(NKError::ErrTxClosed);
+        }
+        if !self.0.writable {
+            return Err(NKError::ErrTxNotWritable);
+        }
         let tx = self.tx();
         let db = tx.db();
+        let opgid = tx.meta.borrow().pgid;
 
+        let start = Instant::now();
         tx.root
             .borrow_mut()
             .rebalance(db.get_page_size() as usize)?;
@@ -84,17 +264,18 @@ impl Tx {
             self._rollback()?;
             return Err(e);
         }
+        db.stats.lock().spill_time += start.elapsed();
         //回收旧的freelist列表
 
         db.freelist
             .try_write()
             .unwrap()
             .free(tx.meta.borrow().txid, unsafe {
-                &*db.page(tx.meta.borrow().freelist)
+                &*tx.raw_page(tx.meta.borrow().freelist)
             });
 
         let size = db.freelist.try_read().unwrap().size();
-        let mut p = match db.allocate(size / db.get_page_size() as usize + 1) {
+        let mut p = match db.allocate(db.page_count(size)) {
             Ok(_p) => _p,
             Err(e) => {
                 self._rollback()?;
@@ -108,31 +289,52 @@ impl Tx {
         tx.meta.borrow_mut().freelist = page.id;
         tx.pages.borrow_mut().insert(page.id, p);
 
-        tx.meta.borrow_mut().root.root = tx.root.borrow().ibucket.root;
-        //write dirty page
-        if let Err(e) = tx.write() {
-            self._rollback()?;
-            return Err(e);
+        tx.meta.borrow_mut().root = tx.root.borrow().ibucket;
+
+        //文件变大了，先扩展文件
+        let pgid = tx.meta.borrow().pgid;
+        if pgid > opgid {
+            if let Err(e) = db.grow((pgid + 1) * db.get_page_size() as u64) {
+                self._rollback()?;
+                return Err(e);
+            }
         }
 
-        //write meta
-        if let Err(e) = tx.write_meta() {
+        //WAL 模式下只同步日志，页面和 meta 等到检查点再写
+        let pages = tx.dirty_pages();
+        let res = match &db.wal {
+            Some(wal) => {
+                let txid = tx.meta.borrow().txid;
+                let mut wal = wal.lock();
+                wal.append(txid, &tx.wal_ops.borrow())
+                    .map(|_| wal.commit(*tx.meta.borrow(), pages))
+            }
+            //write dirty page, sync them before the meta page points to them
+            None => tx.write(pages).and_then(|_| tx.write_meta()),
+        };
+        if let Err(e) = res {
             self._rollback()?;
             return Err(e);
         }
 
+        db.stats.lock().commit_n += 1;
         self.close();
 
         Ok(())
     }
 
     pub(crate) fn close(&self) {
+        if self.0.closed.replace(true) {
+            return;
+        }
         if !self.0.writable {
-            self.0.db().remove_tx(self.clone());
+            self.0.db().remove_tx(&self.0);
             unsafe {
                 self.0.db().mmap.raw().unlock_shared();
             }
         } else {
+            //解除 db 和事务之间的循环引用
+            self.0.db().rwtx.try_write().unwrap().take();
             unsafe {
                 self.0.db().rw_lock.raw().unlock();
             }
@@ -140,48 +342,181 @@ impl Tx {
     }
 }
 
+//没有提交的事务在离开作用域时自动回滚，释放持有的锁
+impl Drop for Tx {
+    fn drop(&mut self) {
+        if !self.0.closed.get() {
+            let _ = self._rollback();
+        }
+    }
+}
+
+struct Checker<'a> {
+    tx: &'a TxImpl,
+    pgid: Pgid,
+    freed: HashSet<Pgid>,
+    reachable: HashSet<Pgid>,
+    errs: Vec<NKError>,
+}
+
+impl Checker<'_> {
+    //标记页及其溢出页可达，页越界或者已经被引用过时返回 false
+    fn mark(&mut self, id: Pgid) -> bool {
+        if id >= self.pgid {
+            self.errs.push(NKError::ErrPageOutOfBounds(id, self.pgid));
+            return false;
+        }
+        let p = match self.tx.page(id) {
+            Ok(p) => unsafe { &*p },
+            Err(e) => {
+                //校验和不对时 overflow 也不可信，只标记这一页
+                self.reachable.insert(id);
+                self.errs.push(e);
+                return false;
+            }
+        };
+        let mut ok = true;
+        for i in id..=id + p.overflow as Pgid {
+            if i >= self.pgid {
+                self.errs.push(NKError::ErrPageOutOfBounds(i, self.pgid));
+                return false;
+            }
+            if !self.reachable.insert(i) {
+                self.errs.push(NKError::ErrPageMultipleReferences(i));
+                ok = false;
+            }
+        }
+        ok
+    }
+
+    //cmp 为 None 表示 bucket 使用了未知的比较器，不检查 key 的顺序
+    fn check_page(&mut self, id: Pgid, cmp: Option<Comparator>) {
+        if !self.mark(id) {
+            return;
+        }
+        let p = unsafe { &*self.tx.raw_page(id) };
+        self.check_elements(id, p, cmp);
+    }
+
+    fn check_elements(&mut self, id: Pgid, p: &Page, cmp: Option<Comparator>) {
+        if p.flags & BranchPageFlag != 0 {
+            let elems = p.branch_page_elements();
+            self.check_order(id, cmp, elems.iter().map(|e| e.key()));
+            for e in elems {
+                self.check_page(e.pgid, cmp);
+            }
+        } else if p.flags & LeafPageFlag != 0 {
+            let elems = p.leaf_page_elements();
+            self.check_order(id, cmp, elems.iter().map(|e| e.key()));
+            for e in elems.iter().filter(|e| e.flags & BucketLeafFlag != 0) {
+                let cmp = Comparator::builtin(Comparator::id_from_flags(e.flags));
+                //value 在页里不一定对齐，先拷贝出来
+                let value = OwnerPage::from_vec(e.value().to_vec());
+                let ibucket = crate::u8_to_struct::<IBucket>(&value.value);
+                if ibucket.root == 0 {
+                    //内联 bucket 的页保存在 value 里，不占用文件中的页
+                    self.check_elements(id, Page::from_buf(&value.value[BucketHeaderSize..]), cmp);
+                } else {
+                    self.check_page(ibucket.root, cmp);
+                }
+            }
+        } else {
+            self.errs.push(NKError::ErrPageInvalidType(id, p.flags));
+        }
+    }
+
+    fn check_order<'k>(
+        &mut self,
+        id: Pgid,
+        cmp: Option<Comparator>,
+        keys: impl Iterator<Item = &'k [u8]>,
+    ) {
+        let Some(cmp) = cmp else {
+            return;
+        };
+        let mut prev: Option<&[u8]> = None;
+        for (i, k) in keys.enumerate() {
+            if prev.is_some_and(|p| (cmp.compare)(p, k) != Ordering::Less) {
+                self.errs.push(NKError::ErrPageUnsortedKeys(id, i));
+            }
+            prev = Some(k);
+        }
+    }
+}
+
 pub(crate) struct TxImpl {
     pub(crate) writable: bool,
+    closed: Cell<bool>,
     dbImpl: Arc<DBImpl>,
     pub(crate) root: RefCell<Bucket>,
     pub(crate) meta: RefCell<Meta>,
     pub(crate) pages: RefCell<HashMap<Pgid, OwnerPage>>,
+    pub(crate) wal_ops: RefCell<Vec<Op>>,
+    //开始时还没写进数据文件的页，只在 WAL 模式下有
+    wal_pages: Option<WalPages>,
 }
 
 impl TxImpl {
-    pub(crate) fn build(writable: bool, db: Arc<DBImpl>) -> TxImpl {
+    pub(crate) fn build(writable: bool, db: Arc<DBImpl>) -> NKResult<TxImpl> {
+        let (meta, wal_pages) = db.snapshot()?;
         let tx = Self {
             writable: writable,
+            closed: Cell::new(false),
             dbImpl: db.clone(),
             root: RefCell::new(Bucket::new(0, Weak::new())),
-            meta: RefCell::new(db.meta()),
+            meta: RefCell::new(meta),
             pages: RefCell::new(HashMap::new()),
+            wal_ops: RefCell::new(Vec::new()),
+            wal_pages,
         };
         tx.root.borrow_mut().ibucket = tx.meta.borrow().root.clone();
-        tx
+        tx.root.borrow_mut().fill_percent = db.fill_percent();
+        if writable && db.page_checksum() {
+            tx.meta.borrow_mut().flags |= FeaturePageChecksum;
+        }
+        Ok(tx)
     }
 
     pub(crate) fn db(&self) -> Arc<DBImpl> {
         self.dbImpl.clone()
     }
 
-    pub(crate) fn write(&self) -> NKResult<()> {
-        let mut pages = self
-            .pages
-            .borrow_mut()
-            .drain()
-            .map(|(k, v)| (k, v))
-            .collect::<Vec<(u64, OwnerPage)>>();
-        pages.sort_by(|a, b| a.0.cmp(&b.0));
+    //WAL 模式下先找还没写进数据文件的页
+    pub(crate) fn page(&self, id: Pgid) -> NKResult<*const Page> {
+        match self.wal_pages.as_ref().and_then(|m| m.get(&id)) {
+            Some(p) => Ok(p.to_page()),
+            None => self.dbImpl.page(id),
+        }
+    }
+
+    //和 page 一样，但不校验页的校验和
+    pub(crate) fn raw_page(&self, id: Pgid) -> *const Page {
+        match self.wal_pages.as_ref().and_then(|m| m.get(&id)) {
+            Some(p) => p.to_page(),
+            None => self.dbImpl.mmap.read_recursive().page(id),
+        }
+    }
+
+    //取出所有脏页，按页号排序并写上校验和
+    pub(crate) fn dirty_pages(&self) -> Vec<OwnerPage> {
+        let mut pages: Vec<OwnerPage> = self.pages.borrow_mut().drain().map(|(_, v)| v).collect();
+        pages.sort_by_key(|p| p.to_page().id);
+        for p in pages.iter_mut() {
+            self.dbImpl.set_checksum(p);
+            if p.to_page().flags & WideCountPageFlag != 0 {
+                self.meta.borrow_mut().flags |= FeatureWideCount;
+            }
+        }
+        pages
+    }
 
+    pub(crate) fn write(&self, pages: Vec<OwnerPage>) -> NKResult<()> {
+        let page_size = self.dbImpl.get_page_size();
         for p in pages.iter() {
-            let page = p.1.to_page();
-            let page_size = self.dbImpl.get_page_size();
-            let offset = page.id * page_size as u64;
-            self.db().write_at(&p.1.value, offset)?;
+            let offset = p.to_page().id * page_size as u64;
+            self.db().write_at(&p.value, offset)?;
         }
-        self.db().sync()?;
-        Ok(())
+        self.db().sync()
     }
 
     pub(crate) fn write_meta(&self) -> NKResult<()> {
@@ -198,3 +533,204 @@ impl TxImpl {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DB, DEFAULT_OPTIONS};
+    use crate::error::{NKError, NKResult};
+    use crate::page::Page;
+    use std::fs::OpenOptions;
+    use std::os::unix::prelude::FileExt;
+
+    #[test]
+    fn test_tx_copy_file() {
+        let options = DEFAULT_OPTIONS.initial_mmap_size(1 << 20);
+        let db = DB::open(&crate::test_db_path("copy_src"), options).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            let b = tx.create_bucket(b"default")?;
+            for i in 0..1000u32 {
+                b.put(format!("key{:04}", i).as_bytes(), b"value")?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let tx = db.begin(false).unwrap();
+        //备份开始之后的修改不会出现在备份里
+        db.update(|tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            b.delete(b"key0000")?;
+            b.put(b"new", b"value")
+        })
+        .unwrap();
+
+        let mut buf = Vec::new();
+        let n = tx.write_to(&mut buf).unwrap();
+        assert_eq!(n, buf.len() as u64);
+        assert_eq!(
+            n,
+            tx.0.meta.borrow().pgid * tx.0.db().get_page_size() as u64
+        );
+
+        let path = crate::test_db_path("copy_dst");
+        assert_eq!(tx.copy_file(&path).unwrap(), n);
+        drop(tx);
+
+        let copy = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+        copy.update(|tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            for i in 0..1000u32 {
+                assert!(b.get(format!("key{:04}", i).as_bytes())?.is_some());
+            }
+            assert!(b.get(b"new")?.is_none());
+            b.put(b"after", b"copy")
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_tx_copy_file_first_meta() {
+        let path = crate::test_db_path("copy_first_meta");
+        let db = DB::open(&path, DEFAULT_OPTIONS.page_size(4096)).unwrap();
+        //meta 1 损坏，只剩事务 id 是 0 的 meta 0
+        let f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.write_at(&[0u8; 4096], 4096).unwrap();
+        let tx = db.begin(false).unwrap();
+        assert_eq!(tx.0.meta.borrow().txid, 0);
+
+        let copy_path = crate::test_db_path("copy_first_meta_dst");
+        tx.copy_file(&copy_path).unwrap();
+        drop(tx);
+        let copy = DB::open(&copy_path, DEFAULT_OPTIONS).unwrap();
+        copy.update(|tx| -> NKResult<()> { tx.create_bucket(b"default").map(|_| ()) })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tx_rollback_error() {
+        let path = crate::test_db_path("rollback_error");
+        let db = DB::open(&path, DEFAULT_OPTIONS.page_size(4096)).unwrap();
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut metas = vec![0u8; 2 * 4096];
+        f.read_exact_at(&mut metas, 0).unwrap();
+
+        let mut tx = db.begin(true).unwrap();
+        //两个 meta 页都损坏时回滚找不到 freelist 页
+        f.write_at(&vec![0u8; metas.len()], 0).unwrap();
+        assert!(tx.rollback().is_err());
+        f.write_at(&metas, 0).unwrap();
+        //写锁已经释放
+        db.update(|tx| -> NKResult<()> { tx.create_bucket(b"default").map(|_| ()) })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tx_check() {
+        let db = DB::open(&crate::test_db_path("check"), DEFAULT_OPTIONS).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            tx.create_bucket(b"a")?.put(b"abc", b"123")?;
+            let b = tx.create_bucket(b"b")?;
+            for i in 0..2000u32 {
+                b.put(format!("key{:04}", i).as_bytes(), &[0u8; 50])?;
+            }
+            b.create_bucket(b"nested")?.put(b"abc", b"123")
+        })
+        .unwrap();
+        db.update(|tx| -> NKResult<()> {
+            let b = tx.bucket(b"b")?;
+            for i in (0..2000u32).step_by(3) {
+                b.delete(format!("key{:04}", i).as_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let tx = db.begin(false).unwrap();
+        assert!(tx.check().is_empty());
+        let root = tx.0.meta.borrow().root.root;
+        let pgid = tx.0.meta.borrow().pgid;
+        drop(tx);
+
+        //交换根页面里 bucket a 和 b 的 key
+        let page_size = db.0.get_page_size();
+        let mut buf = vec![0u8; page_size];
+        buf.copy_from_slice(unsafe {
+            std::slice::from_raw_parts(db.0.page(root).unwrap() as *const u8, page_size)
+        });
+        let p = Page::from_buf_mut(&mut buf);
+        let (e0, e1) = (
+            p.leaf_page_element(0).as_ptr(),
+            p.leaf_page_element(1).as_ptr(),
+        );
+        let (k0, k1) = (
+            p.leaf_page_element(0).pos as usize,
+            p.leaf_page_element(1).pos as usize,
+        );
+        unsafe {
+            std::ptr::swap(e0.add(k0) as *mut u8, e1.add(k1) as *mut u8);
+        }
+        db.0.write_at(&buf, root * page_size as u64).unwrap();
+
+        //把 meta 的 pgid 加一，最后一页既不可达也不在 freelist 里
+        let mut meta = db.0.meta().unwrap();
+        meta.pgid += 1;
+        meta.txid += 1;
+        let mut buf = vec![0u8; page_size];
+        meta.write(Page::from_buf_mut(&mut buf));
+        db.0.write_at(&buf, (meta.txid % 2) * page_size as u64)
+            .unwrap();
+
+        let tx = db.begin(false).unwrap();
+        let errs = tx.check();
+        assert_eq!(errs.len(), 2);
+        assert!(matches!(errs[0], NKError::ErrPageUnsortedKeys(id, 1) if id == root));
+        assert!(matches!(errs[1], NKError::ErrPageLeaked(id) if id == pgid));
+    }
+
+    #[test]
+    fn test_tx_bucket_ref() {
+        let db = DB::open(&crate::test_db_path("bucket_ref"), DEFAULT_OPTIONS).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            tx.create_bucket(b"a")?.put(b"key", b"value-a")?;
+            let b = tx.create_bucket(b"b")?;
+            b.put(b"key", b"value-b")?;
+            b.create_bucket(b"nested")?.put(b"key", b"value-c")?;
+            Ok(())
+        })
+        .unwrap();
+        db.view(|tx| -> NKResult<()> {
+            //同时持有多个 bucket 里的 value，不需要拷贝
+            let a = tx.bucket_ref(b"a")?;
+            let b = tx.bucket_ref(b"b")?;
+            let va = a.get(b"key")?.unwrap();
+            let vb = b.get(b"key")?.unwrap();
+            let vc = b.bucket_ref(b"nested")?.get(b"key")?.unwrap();
+            let again = tx.bucket_ref(b"a")?.get(b"key")?.unwrap();
+            assert_eq!(
+                (&*va, &*vb, &*vc),
+                (&b"value-a"[..], &b"value-b"[..], &b"value-c"[..])
+            );
+            assert_eq!(va.as_ptr(), again.as_ptr());
+            assert_eq!(a.range::<[u8], _>(..).count(), 1);
+            assert!(matches!(
+                tx.bucket_ref(b"none"),
+                Err(NKError::ErrBucketNotFound)
+            ));
+            Ok(())
+        })
+        .unwrap();
+        //写事务里读到的是还没有提交的 value
+        db.update(|tx| -> NKResult<()> {
+            tx.bucket(b"a")?.put(b"key", b"changed")?;
+            let a = tx.bucket_ref(b"a")?;
+            let b = tx.bucket_ref(b"b")?;
+            assert_eq!(a.get(b"key")?.as_deref(), Some(&b"changed"[..]));
+            assert_eq!(b.get(b"key")?.as_deref(), Some(&b"value-b"[..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+}
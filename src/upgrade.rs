@@ -0,0 +1,175 @@
+use crate::db::{flock, read_page_size};
+use crate::error::{NKError, NKResult};
+use crate::page::{ChecksumPageFlag, FeatureWideCount, FreeListPageFlag, Page, WideCountPageFlag};
+use crate::version;
+use std::fs::OpenOptions;
+use std::os::unix::prelude::FileExt;
+use std::time::Duration;
+
+/// Upgrades the database file at `path`, written by an older version of the
+/// format, in place. The meta pages are rewritten, and a freelist page with
+/// more than 0xFFFF ids is marked as using the wide element count. Files
+/// already at the current version are left unchanged. The file must not be
+/// open elsewhere.
+pub fn upgrade(path: &str) -> NKResult<()> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(NKError::DBOpenFail)?;
+    flock(&f, true, Duration::from_secs(0))?;
+    let page_size = read_page_size(&f)?;
+
+    let mut pages = Vec::new();
+    for id in 0..2u64 {
+        let mut buf = vec![0u8; page_size];
+        f.read_exact_at(&mut buf, id * page_size as u64)
+            .map_err(|_e| ("can't read meta page", _e))?;
+        let m = Page::from_buf(&buf).meta();
+        //损坏的 meta 页留给 DB::repair_meta
+        if m.validate().is_ok() && m.version < version {
+            pages.push((m.txid, id, buf));
+        }
+    }
+    //先写事务 id 小的 meta，中途失败时文件还是旧版本，可以重新升级
+    pages.sort_by_key(|p| p.0);
+    for (_, id, mut buf) in pages {
+        let m = Page::from_buf_mut(&mut buf).meta_mut();
+        if upgrade_freelist(&f, page_size, m.freelist)? {
+            m.flags |= FeatureWideCount;
+        }
+        m.version = version;
+        m.checksum = m.sum64();
+        f.write_at(&buf, id * page_size as u64)
+            .map_err(|_e| ("can't write meta page", _e))?;
+        f.sync_data().map_err(|_e| ("can't sync file", _e))?;
+    }
+    Ok(())
+}
+
+//版本 2 的 freelist 超过 0xFFFF 个时 count 是 0xFFFF，页头之后的 8 个字节存放实际的个数，
+//和 WideCountPageFlag 的布局一样，只要补上 flag。返回 freelist 页是否用了 WideCountPageFlag
+fn upgrade_freelist(f: &std::fs::File, page_size: usize, id: u64) -> NKResult<bool> {
+    let mut buf = vec![0u8; page_size];
+    f.read_exact_at(&mut buf, id * page_size as u64)
+        .map_err(|_e| ("can't read freelist page", _e))?;
+    let p = Page::from_buf(&buf);
+    if p.flags & FreeListPageFlag == 0 || p.count != 0xFFFF {
+        return Ok(false);
+    }
+    if p.flags & WideCountPageFlag == 0 {
+        //带校验和的页要整页重新计算
+        buf.resize((p.overflow as usize + 1) * page_size, 0);
+        f.read_exact_at(&mut buf, id * page_size as u64)
+            .map_err(|_e| ("can't read freelist page", _e))?;
+        let p = Page::from_buf_mut(&mut buf);
+        p.flags |= WideCountPageFlag;
+        if p.flags & ChecksumPageFlag != 0 {
+            Page::set_checksum(&mut buf);
+        }
+        f.write_at(&buf, id * page_size as u64)
+            .map_err(|_e| ("can't write freelist page", _e))?;
+        f.sync_data().map_err(|_e| ("can't sync file", _e))?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{DB, DEFAULT_OPTIONS};
+    use crate::min_version;
+
+    //把两个 meta 页改成旧版本
+    fn downgrade(path: &str) {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        for id in 0..2u64 {
+            let mut buf = vec![0u8; 4096];
+            f.read_exact_at(&mut buf, id * 4096).unwrap();
+            let m = Page::from_buf_mut(&mut buf).meta_mut();
+            m.version = min_version;
+            m.checksum = m.sum64();
+            f.write_at(&buf, id * 4096).unwrap();
+        }
+    }
+
+    //把两个 meta 指向的 freelist 页改成版本 2 超过 0xFFFF 个 id 时的格式：count 是 0xFFFF，
+    //第一个元素存放实际的个数
+    fn legacy_freelist(path: &str) {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        for id in 0..2u64 {
+            let mut buf = vec![0u8; 4096];
+            f.read_exact_at(&mut buf, id * 4096).unwrap();
+            let offset = Page::from_buf(&buf).meta().freelist * 4096;
+            f.read_exact_at(&mut buf, offset).unwrap();
+            let p = Page::from_buf_mut(&mut buf);
+            if p.count == 0xFFFF {
+                continue;
+            }
+            let ids = p.freelist().to_vec();
+            p.count = 0xFFFF;
+            unsafe {
+                let m = std::slice::from_raw_parts_mut(p.data_ptr_mut() as *mut u64, ids.len() + 1);
+                m[0] = ids.len() as u64;
+                m[1..].copy_from_slice(&ids);
+            }
+            if p.flags & ChecksumPageFlag != 0 {
+                Page::set_checksum(&mut buf);
+            }
+            f.write_at(&buf, offset).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let path = crate::test_db_path("upgrade");
+        let options = DEFAULT_OPTIONS.page_size(4096);
+        DB::open(&path, options)
+            .unwrap()
+            .update(|tx| -> NKResult<()> {
+                tx.create_bucket(b"default")?.put(b"key", b"value")?;
+                Ok(())
+            })
+            .unwrap();
+        downgrade(&path);
+        legacy_freelist(&path);
+
+        assert!(matches!(
+            DB::open(&path, options),
+            Err(NKError::ErrUpgradeRequired)
+        ));
+        DB::open(&path, options.read_only(true))
+            .unwrap()
+            .view(|tx| -> NKResult<()> {
+                assert_eq!(
+                    tx.bucket(b"default")?.get(b"key")?.as_deref(),
+                    Some(&b"value"[..])
+                );
+                Ok(())
+            })
+            .unwrap();
+
+        upgrade(&path).unwrap();
+        upgrade(&path).unwrap();
+        let db = DB::open(&path, options).unwrap();
+        db.update(|tx| -> NKResult<()> {
+            tx.bucket(b"default")?.put(b"key2", b"value2")?;
+            Ok(())
+        })
+        .unwrap();
+        let meta = db.0.meta().unwrap();
+        assert_eq!(meta.version, version);
+        assert_ne!(meta.flags & FeatureWideCount, 0);
+        let mut tx = db.begin(false).unwrap();
+        assert!(tx.check().is_empty());
+        tx.rollback().unwrap();
+    }
+}
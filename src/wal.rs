@@ -0,0 +1,599 @@
+use crate::bucket::Bucket;
+use crate::comparator::Comparator;
+use crate::compression::Compression;
+use crate::db::DB;
+use crate::error::{NKError, NKResult};
+use crate::page::{Meta, OwnerPage, Pgid};
+use crate::tx::Txid;
+use fnv::FnvHasher;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+const RECORD_HEADER_SIZE: usize = 4 + 8;
+
+//一个事务提交的所有修改
+pub(crate) type Record = (Txid, Vec<Op>);
+
+//已经提交、还没写进数据文件的页。事务开始时拿走一份引用，检查点之后旧的页也不会被释放
+pub(crate) type WalPages = Arc<HashMap<Pgid, Arc<OwnerPage>>>;
+
+/// A change recorded by a writable transaction in WAL mode. `path` names the
+/// bucket the change applies to, starting from the root bucket.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Op {
+    Put {
+        path: Vec<Vec<u8>>,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        path: Vec<Vec<u8>>,
+        key: Vec<u8>,
+    },
+    CreateBucket {
+        path: Vec<Vec<u8>>,
+        key: Vec<u8>,
+        comparator: u16,
+    },
+    DeleteBucket {
+        path: Vec<Vec<u8>>,
+        key: Vec<u8>,
+    },
+    SetSequence {
+        path: Vec<Vec<u8>>,
+        sequence: u64,
+    },
+    SetCompression {
+        path: Vec<Vec<u8>>,
+        compression: u8,
+    },
+}
+
+//提交的页面留在内存里，检查点时才写进数据文件。日志只在崩溃后重放，检查点不读日志
+pub(crate) struct Wal {
+    file: File,
+    //已经提交到日志、还没有做检查点的最新 meta，读写事务从这里开始
+    pub(crate) meta: Option<Meta>,
+    pub(crate) pages: WalPages,
+}
+
+impl Wal {
+    //打开日志并读出完整的记录，末尾写了一半的记录会被截掉
+    pub(crate) fn open(path: &str) -> NKResult<(Wal, Vec<Record>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(NKError::DBOpenFail)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)
+            .map_err(|_e| ("can't read wal", _e))?;
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while let Some((txid, ops, len)) = decode_record(&buf[pos..]) {
+            records.push((txid, ops));
+            pos += len;
+        }
+        if pos < buf.len() {
+            file.set_len(pos as u64)
+                .map_err(|_e| ("can't truncate wal", _e))?;
+        }
+        let wal = Wal {
+            file,
+            meta: None,
+            pages: Arc::default(),
+        };
+        Ok((wal, records))
+    }
+
+    //追加一个事务的记录，只同步日志文件
+    pub(crate) fn append(&mut self, txid: Txid, ops: &[Op]) -> NKResult<()> {
+        let mut body = txid.to_le_bytes().to_vec();
+        for op in ops {
+            op.encode(&mut body);
+        }
+        let mut h = FnvHasher::default();
+        h.write(&body);
+        let mut buf = Vec::with_capacity(RECORD_HEADER_SIZE + body.len());
+        buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&h.finish().to_le_bytes());
+        buf.extend_from_slice(&body);
+        self.file
+            .write_all(&buf)
+            .map_err(|_e| ("can't write wal", _e))?;
+        self.file.sync_data().map_err(|_e| ("can't sync wal", _e))?;
+        Ok(())
+    }
+
+    //日志已经同步，提交的页和 meta 对之后开始的事务可见
+    pub(crate) fn commit(&mut self, meta: Meta, pages: Vec<OwnerPage>) {
+        let m = Arc::make_mut(&mut self.pages);
+        for p in pages {
+            m.insert(p.to_page().id, Arc::new(p));
+        }
+        self.meta = Some(meta);
+    }
+
+    pub(crate) fn reset(&mut self) -> NKResult<()> {
+        self.file
+            .set_len(0)
+            .map_err(|_e| ("can't truncate wal", _e))?;
+        self.file.sync_data().map_err(|_e| ("can't sync wal", _e))?;
+        self.meta = None;
+        self.pages = Arc::default();
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> NKResult<u64> {
+        Ok(self
+            .file
+            .metadata()
+            .map_err(|_e| ("can't stat wal", _e))?
+            .len())
+    }
+}
+
+//记录格式: 长度 u32 | 校验和 u64 | txid u64 | op...，长度和校验和覆盖 txid 和 op
+fn decode_record(buf: &[u8]) -> Option<(Txid, Vec<Op>, usize)> {
+    let mut r = Reader(buf);
+    let len = r.u32()? as usize;
+    let checksum = r.u64()?;
+    let body = r.take(len)?;
+    let mut h = FnvHasher::default();
+    h.write(body);
+    if h.finish() != checksum {
+        return None;
+    }
+    let mut r = Reader(body);
+    let txid = r.u64()?;
+    let mut ops = Vec::new();
+    while !r.0.is_empty() {
+        ops.push(Op::decode(&mut r)?);
+    }
+    Some((txid, ops, RECORD_HEADER_SIZE + len))
+}
+
+fn put_bytes(buf: &mut Vec<u8>, b: &[u8]) {
+    buf.extend_from_slice(&(b.len() as u32).to_le_bytes());
+    buf.extend_from_slice(b);
+}
+
+fn put_path(buf: &mut Vec<u8>, path: &[Vec<u8>]) {
+    buf.extend_from_slice(&(path.len() as u32).to_le_bytes());
+    for k in path {
+        put_bytes(buf, k);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (b, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(b)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let n = self.u32()? as usize;
+        Some(self.take(n)?.to_vec())
+    }
+
+    fn path(&mut self) -> Option<Vec<Vec<u8>>> {
+        let n = self.u32()? as usize;
+        (0..n).map(|_| self.bytes()).collect()
+    }
+}
+
+impl Op {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Op::Put { path, key, value } => {
+                buf.push(0);
+                put_path(buf, path);
+                put_bytes(buf, key);
+                put_bytes(buf, value);
+            }
+            Op::Delete { path, key } => {
+                buf.push(1);
+                put_path(buf, path);
+                put_bytes(buf, key);
+            }
+            Op::CreateBucket {
+                path,
+                key,
+                comparator,
+            } => {
+                buf.push(2);
+                put_path(buf, path);
+                put_bytes(buf, key);
+                buf.extend_from_slice(&comparator.to_le_bytes());
+            }
+            Op::DeleteBucket { path, key } => {
+                buf.push(3);
+                put_path(buf, path);
+                put_bytes(buf, key);
+            }
+            Op::SetSequence { path, sequence } => {
+                buf.push(4);
+                put_path(buf, path);
+                buf.extend_from_slice(&sequence.to_le_bytes());
+            }
+            Op::SetCompression { path, compression } => {
+                buf.push(5);
+                put_path(buf, path);
+                buf.push(*compression);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Op> {
+        let op = match r.u8()? {
+            0 => Op::Put {
+                path: r.path()?,
+                key: r.bytes()?,
+                value: r.bytes()?,
+            },
+            1 => Op::Delete {
+                path: r.path()?,
+                key: r.bytes()?,
+            },
+            2 => Op::CreateBucket {
+                path: r.path()?,
+                key: r.bytes()?,
+                comparator: r.u16()?,
+            },
+            3 => Op::DeleteBucket {
+                path: r.path()?,
+                key: r.bytes()?,
+            },
+            4 => Op::SetSequence {
+                path: r.path()?,
+                sequence: r.u64()?,
+            },
+            5 => Op::SetCompression {
+                path: r.path()?,
+                compression: r.u8()?,
+            },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn path(&self) -> &[Vec<u8>] {
+        match self {
+            Op::Put { path, .. }
+            | Op::Delete { path, .. }
+            | Op::CreateBucket { path, .. }
+            | Op::DeleteBucket { path, .. }
+            | Op::SetSequence { path, .. }
+            | Op::SetCompression { path, .. } => path,
+        }
+    }
+
+    fn apply(&self, root: &mut Bucket) -> NKResult<()> {
+        let mut b = root;
+        for k in self.path() {
+            b = b.stored_bucket(k)?;
+        }
+        match self {
+            Op::Put { key, value, .. } => b.put(key, value),
+            Op::Delete { key, .. } => b.delete(key),
+            Op::CreateBucket {
+                key, comparator, ..
+            } => {
+                let cmp = Comparator::builtin(*comparator)
+                    .ok_or(NKError::ErrComparatorMismatch(*comparator))?;
+                b.create_bucket_with_comparator(key, cmp).map(|_| ())
+            }
+            Op::DeleteBucket { key, .. } => b.delete_bucket(key),
+            Op::SetSequence { sequence, .. } => b.set_sequence(*sequence),
+            Op::SetCompression { compression, .. } => {
+                b.set_compression(Compression::from_id(*compression)?)
+            }
+        }
+    }
+}
+
+//重放检查点之后提交的事务。所有记录合并到一个事务里提交，事务 id 取最后一条记录的 id，
+//重放中途崩溃时已经重放过的记录会被跳过。合并的事务不再写日志，原来的记录在检查点之前一直保留
+pub(crate) fn replay(db: &DB, records: Vec<Record>) -> NKResult<()> {
+    let base = db.0.meta()?.txid;
+    let records: Vec<_> = records.into_iter().filter(|r| r.0 > base).collect();
+    if let Some((last, _)) = records.last() {
+        let mut tx = db.begin(true)?;
+        tx.0.meta.borrow_mut().txid = *last;
+        let root = unsafe { &mut *tx.0.root.as_ptr() };
+        for op in records.iter().flat_map(|r| r.1.iter()) {
+            op.apply(root)?;
+        }
+        tx.0.wal_ops.borrow_mut().clear();
+        tx.commit()?;
+    }
+    db.0.checkpoint()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DEFAULT_OPTIONS;
+    use std::time::Duration;
+
+    fn wal_path(path: &str) -> String {
+        let wal = format!("{}-wal", path);
+        let _ = std::fs::remove_file(&wal);
+        wal
+    }
+
+    #[test]
+    fn test_wal_record() {
+        let ops = vec![
+            Op::CreateBucket {
+                path: vec![],
+                key: b"a".to_vec(),
+                comparator: 2,
+            },
+            Op::Put {
+                path: vec![b"a".to_vec()],
+                key: b"k".to_vec(),
+                value: b"v".to_vec(),
+            },
+            Op::SetSequence {
+                path: vec![b"a".to_vec()],
+                sequence: 9,
+            },
+            Op::SetCompression {
+                path: vec![b"a".to_vec()],
+                compression: 1,
+            },
+        ];
+        let path = wal_path(&crate::test_db_path("wal_record"));
+        let (mut wal, records) = Wal::open(&path).unwrap();
+        assert!(records.is_empty());
+        wal.append(7, &ops).unwrap();
+        wal.append(8, &[]).unwrap();
+        let len = wal.len().unwrap();
+        //写了一半的记录在打开时被截掉
+        wal.file.write_all(&[1, 2, 3]).unwrap();
+        let (wal, records) = Wal::open(&path).unwrap();
+        assert_eq!(records, vec![(7, ops), (8, vec![])]);
+        assert_eq!(wal.len().unwrap(), len);
+    }
+
+    #[test]
+    fn test_wal_replay() {
+        let path = crate::test_db_path("wal_replay");
+        let wal = wal_path(&path);
+        let options = DEFAULT_OPTIONS
+            .wal(true)
+            .checkpoint_interval(Duration::from_secs(3600));
+        {
+            let db = DB::open(&path, options).unwrap();
+            db.update(|tx| -> NKResult<()> {
+                let b = tx.create_bucket(b"default")?;
+                for i in 0..1000u32 {
+                    b.put(format!("key{:04}", i).as_bytes(), b"value")?;
+                }
+                b.create_bucket_with_comparator(b"reverse", crate::comparator::REVERSE)?
+                    .put(b"k", b"v")?;
+                tx.create_bucket(b"deleted")?;
+                Ok(())
+            })
+            .unwrap();
+            db.update(|tx| -> NKResult<()> {
+                let b = tx.bucket(b"default")?;
+                b.next_sequence()?;
+                let mut c = b.cursor();
+                c.seek(b"key0500")?;
+                c.delete()?;
+                b.delete(b"key0501")?;
+                tx.delete_bucket(b"deleted")
+            })
+            .unwrap();
+            //提交后的数据在日志里，数据文件的 meta 还没有更新
+            assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+            assert_eq!(db.0.mmap.read().meta().unwrap().txid, 1);
+        }
+        let check = |tx: &mut crate::tx::Tx| -> NKResult<()> {
+            assert!(tx.check().is_empty());
+            assert!(matches!(
+                tx.bucket(b"deleted"),
+                Err(NKError::ErrBucketNotFound)
+            ));
+            let b = tx.bucket(b"default")?;
+            assert_eq!(b.sequence(), 1);
+            assert_eq!(b.get(b"key0499")?.as_deref(), Some(&b"value"[..]));
+            assert_eq!(b.get(b"key0500")?, None);
+            assert_eq!(b.get(b"key0501")?, None);
+            let r = b.bucket_with_comparator(b"reverse", crate::comparator::REVERSE)?;
+            assert_eq!(r.get(b"k")?.as_deref(), Some(&b"v"[..]));
+            Ok(())
+        };
+        //只读打开没法重放日志
+        assert!(matches!(
+            DB::open(&path, DEFAULT_OPTIONS.read_only(true)),
+            Err(NKError::ErrWalReplayRequired)
+        ));
+        {
+            let db = DB::open(&path, options).unwrap();
+            assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+            db.view(check).unwrap();
+            db.update(|tx| -> NKResult<()> { tx.bucket(b"default")?.put(b"after", b"replay") })
+                .unwrap();
+            assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+        }
+        //不开 WAL 打开也会重放日志
+        {
+            let db = DB::open(&path, DEFAULT_OPTIONS).unwrap();
+            assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+            db.view(check).unwrap();
+            db.update(|tx| -> NKResult<()> { tx.bucket(b"default")?.put(b"without", b"wal") })
+                .unwrap();
+        }
+        let db = DB::open(&path, options).unwrap();
+        db.view(check).unwrap();
+        db.view(|tx| -> NKResult<()> {
+            let b = tx.bucket(b"default")?;
+            assert_eq!(b.get(b"after")?.as_deref(), Some(&b"replay"[..]));
+            assert_eq!(b.get(b"without")?.as_deref(), Some(&b"wal"[..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_wal_pages() {
+        let path = crate::test_db_path("wal_pages");
+        let wal = wal_path(&path);
+        let options = DEFAULT_OPTIONS
+            .wal(true)
+            .checkpoint_interval(Duration::from_secs(3600));
+        let db = DB::open(&path, options).unwrap();
+        let value = b"written at checkpoint".repeat(4);
+        db.update(|tx| -> NKResult<()> { tx.create_bucket(b"default")?.put(b"key", &value) })
+            .unwrap();
+        let contains = |path: &str| {
+            std::fs::read(path)
+                .unwrap()
+                .windows(value.len())
+                .any(|w| w == value)
+        };
+        //提交只写日志，页面留在内存里
+        assert!(contains(&wal));
+        assert!(!contains(&path));
+
+        let mut tx = db.begin(false).unwrap();
+        let copy = crate::test_db_path("wal_pages_copy");
+        tx.copy_file(&copy).unwrap();
+        db.checkpoint().unwrap();
+        assert!(contains(&path));
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+        //检查点之前开始的读事务还能读
+        assert_eq!(
+            tx.bucket(b"default")
+                .unwrap()
+                .get(b"key")
+                .unwrap()
+                .as_deref(),
+            Some(&value[..])
+        );
+        drop(tx);
+        drop(db);
+
+        for path in [&path, &copy] {
+            let db = DB::open(path, DEFAULT_OPTIONS).unwrap();
+            db.view(|tx| -> NKResult<()> {
+                assert!(tx.check().is_empty());
+                assert_eq!(
+                    tx.bucket(b"default")?.get(b"key")?.as_deref(),
+                    Some(&value[..])
+                );
+                Ok(())
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_wal_custom_comparator() {
+        let path = crate::test_db_path("wal_custom_comparator");
+        wal_path(&path);
+        let custom = Comparator {
+            id: 20,
+            compare: |a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+        };
+        //不开 WAL 时可以使用自定义比较器
+        DB::open(&path, DEFAULT_OPTIONS)
+            .unwrap()
+            .update(|tx| -> NKResult<()> {
+                tx.create_bucket_with_comparator(b"custom", custom)?
+                    .create_bucket(b"nested")?
+                    .put(b"k", b"v")
+            })
+            .unwrap();
+        let options = DEFAULT_OPTIONS.wal(true);
+        {
+            let db = DB::open(&path, options).unwrap();
+            let r = db.update(|tx| tx.create_bucket_with_comparator(b"new", custom).map(|_| ()));
+            assert!(matches!(r, Err(NKError::ErrWalComparator(20))));
+            let r = db.update(|tx| {
+                tx.bucket_with_comparator(b"custom", custom)?
+                    .bucket(b"nested")?
+                    .put(b"k", b"v2")
+            });
+            assert!(matches!(r, Err(NKError::ErrWalComparator(20))));
+            db.update(|tx| -> NKResult<()> {
+                tx.create_bucket_with_comparator(b"reverse", crate::comparator::REVERSE)?
+                    .put(b"k", b"v")
+            })
+            .unwrap();
+        }
+        //日志里没有重放不了的记录
+        let db = DB::open(&path, options).unwrap();
+        db.view(|tx| -> NKResult<()> {
+            let b = tx.bucket_with_comparator(b"custom", custom)?;
+            assert_eq!(b.bucket(b"nested")?.get(b"k")?.as_deref(), Some(&b"v"[..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_wal_checkpoint() {
+        let path = crate::test_db_path("wal_checkpoint");
+        let wal = wal_path(&path);
+        let options = DEFAULT_OPTIONS
+            .wal(true)
+            .checkpoint_interval(Duration::from_millis(20));
+        let db = DB::open(&path, options).unwrap();
+        for i in 0..20u32 {
+            db.update(|tx| -> NKResult<()> {
+                tx.create_bucket_if_not_exists(b"default")?
+                    .put(&i.to_be_bytes(), &[0u8; 1000])
+            })
+            .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+        assert_eq!(
+            db.0.mmap.read().meta().unwrap().txid,
+            db.0.meta().unwrap().txid
+        );
+        db.view(|tx| -> NKResult<()> {
+            assert!(tx.check().is_empty());
+            assert_eq!(tx.bucket(b"default")?.range::<[u8], _>(..).count(), 20);
+            Ok(())
+        })
+        .unwrap();
+        assert!(DB::open(
+            &crate::test_db_path("wal_read_only"),
+            options.read_only(true)
+        )
+        .is_err());
+    }
+}